@@ -0,0 +1,87 @@
+use std::io::{self, Read};
+use std::vec::IntoIter;
+
+use crate::{demux, Hdr, RErr, RkReader};
+
+/// Reads the PCM data of several RKPI2 streams one after the
+/// other, as if they were one. Each segment is demuxed when the
+/// one before it runs out.
+///
+/// With `require_compatible`, a segment whose header isn't
+/// `is_compatible_for_concat` with the first one fails the read
+/// reaching it with `RErr::Mismatch`. Without it the layout can
+/// change between segments, and it's up to the caller to look
+/// at `header` as it goes.
+pub struct RkStreamReader<R: Read> {
+    cur: RkReader<R>,
+    first: Hdr,
+    rest: IntoIter<R>,
+    require_compatible: bool,
+}
+
+impl<R: Read> RkStreamReader<R> {
+    /// Demux the first of `segments` right away, failing with
+    /// `RErr::Truncated` if there's none.
+    pub fn new(segments: Vec<R>, require_compatible: bool)
+        -> Result<Self, RErr> {
+        let mut rest = segments.into_iter();
+        let cur = demux(rest.next().ok_or(RErr::Truncated)?)?;
+
+        Ok(RkStreamReader { first: *cur.header(), cur, rest,
+                            require_compatible })
+    }
+
+    /// Header of the segment being read.
+    pub fn header(&self) -> &Hdr {
+        self.cur.header()
+    }
+}
+
+impl<R: Read> Read for RkStreamReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.cur.read(buf)?;
+            if n > 0 || buf.is_empty() { return Ok(n) }
+
+            let next = match self.rest.next() {
+                Some(r) => demux(r)?,
+                None => return Ok(0)
+            };
+            if self.require_compatible
+               && !self.first.is_compatible_for_concat(next.header()) {
+                return Err(RErr::Mismatch.into())
+            }
+
+            self.cur = next;
+        }
+    }
+}
+
+#[test]
+fn concat_segments() {
+    use crate::{encode_to_vec, Fmt};
+
+    let mono = Hdr { format: Fmt::Int8, rate: 8000, channels: 1,
+                     frames: None };
+    let a = encode_to_vec(mono, &[1, 2], None).unwrap();
+    let b = encode_to_vec(Hdr { frames: Some(1), ..mono }, &[3], None).unwrap();
+    let stereo = encode_to_vec(Hdr { channels: 2, ..mono }, &[4, 5], None)
+        .unwrap();
+
+    let mut out = Vec::new();
+    RkStreamReader::new(vec![&a[..], &b[..]], true).unwrap()
+        .read_to_end(&mut out).unwrap();
+    assert!(out == [1, 2, 3]);
+
+    let mut r = RkStreamReader::new(vec![&a[..], &stereo[..]], true).unwrap();
+    let e = r.read_to_end(&mut Vec::new()).unwrap_err();
+    assert!(matches!(RErr::from(e), RErr::Mismatch));
+
+    let mut r = RkStreamReader::new(vec![&a[..], &stereo[..]], false).unwrap();
+    out.clear();
+    r.read_to_end(&mut out).unwrap();
+    assert!(out == [1, 2, 4, 5] && r.header().channels == 2);
+
+    assert!(matches!(RkStreamReader::<&[u8]>::new(vec![], true),
+                     Err(RErr::Truncated)));
+}
@@ -0,0 +1,567 @@
+use core::convert::TryFrom;
+
+use crate::{Codec, Fmt, Hdr, RErr};
+
+pub(crate) const SAMPLERATES: [u32; 8] = [
+    8000, 12000, 22050, 32000, 44100,
+    64000, 96000, 192000 ];
+
+// the index of a samplerate is a 3-bit field in the header.
+const _: () = assert!(SAMPLERATES.len() == 8);
+
+/// Start code of standard RKPI2 streams, the top six bits of
+/// the first byte.
+pub(crate) const START_CODE: u8 = 0x3d;
+
+/// Whether `byte0` begins a standard RKPI2 stream, i.e. has the
+/// start code in its top six bits. Cheap enough to reject most
+/// garbage on its own before parsing any further.
+pub const fn check_start_code(byte0: u8) -> bool {
+    byte0 >> 2 == START_CODE
+}
+
+/// Format code signalling that an extended header follows
+/// the first two bytes, carrying the real format code and
+/// a byte of flags for the optional fields after it.
+const FMT_EXT: u8 = 7;
+
+/// Revision of the layout this crate reads and writes, kept in
+/// the top two bits of the extended header's format byte. A
+/// header without the extension is always version 0, which is:
+///
+/// - 6-bit start code, compression flag, 3-bit format code,
+///   3-bit samplerate index, 3-bit channel count minus one;
+/// - with format code 7, a byte of format code (3 bits, the
+///   rest reserved) and version, then a byte of flags; each
+///   flag set adds its field after that, in order of the bit.
+const VERSION: u8 = 0;
+
+/// Extended header flag: a little-endian `u64` frame count.
+const EXT_FRAMES: u8 = 1;
+
+/// Extended header flag: a byte with the codec of the payload,
+/// for codecs the compression flag can't tell apart. Never
+/// written, as that's only needed past `Codec::Zstd`.
+const EXT_CODEC: u8 = 2;
+
+/// Extended header flag: the PCM data has runs of silence
+/// collapsed, see `utils::rle`.
+const EXT_RLE: u8 = 4;
+
+/// Extended header flag: the PCM data is followed by its CRC-32,
+/// see `utils::crc`.
+const EXT_CRC: u8 = 8;
+
+/// Extended header flag: a block of key/value tags, a `u32` LE
+/// length followed by that many bytes of entries. Each entry is
+/// a key and a value, both UTF-8 strings after their `u32` LE
+/// length.
+const EXT_TAGS: u8 = 16;
+
+/// Extended header flag: samples are stored as differences from
+/// the previous one of their channel, see `utils::delta`.
+const EXT_DELTA: u8 = 32;
+
+/// Flags of the extended header this version knows about.
+const EXT_KNOWN: u8 = EXT_FRAMES | EXT_CODEC | EXT_RLE | EXT_CRC | EXT_TAGS
+                    | EXT_DELTA;
+
+/// How the PCM data is coded into the payload.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub(crate) struct Coding {
+    pub(crate) codec: Codec,
+    /// Silent runs collapsed ahead of the codec.
+    pub(crate) rle: bool,
+    /// CRC-32 after the PCM data.
+    pub(crate) crc: bool,
+    /// Samples stored as differences.
+    pub(crate) delta: bool,
+}
+
+impl From<Codec> for Coding {
+    fn from(codec: Codec) -> Self {
+        Coding { codec, rle: false, crc: false, delta: false }
+    }
+}
+
+/// Samplerate stored at an index of the table, the inverse of
+/// `Hdr::sample_rate_index`. Fails with `RErr::Rate` past its
+/// last slot. Const, so `fields` can go through it.
+pub const fn rate_from_index(idx: u8) -> Result<u32, RErr> {
+    let i = idx as usize;
+    if i >= SAMPLERATES.len() { return Err(RErr::Rate) }
+    Ok(SAMPLERATES[i])
+}
+
+/// Pack the fields of the minimal two byte header, checking
+/// that `rate_idx` indexes the samplerate table and that there
+/// are 1 to 8 `channels`. This is only the bit-packing, with no
+/// frame count or other extended fields.
+pub fn encode_header_bits(format: Fmt, rate_idx: u8, channels: u8,
+                          compressed: bool) -> Result<[u8; 2], RErr> {
+    rate_from_index(rate_idx)?;
+    if !(1..=8).contains(&channels) { return Err(RErr::Channels) }
+
+    Ok(pack(START_CODE, format as u8, rate_idx, channels, compressed))
+}
+
+fn pack(start: u8, format: u8, rate_idx: u8, channels: u8,
+        compressed: bool) -> [u8; 2] {
+    [
+        start              << 2|
+        (compressed as u8) << 1|
+        format             >> 2,
+        (format & 3)       << 6|
+        rate_idx           << 3|
+        (channels - 1)
+    ]
+}
+
+/// Validate a header and code it to its bytes after the 6-bit
+/// `start` code, in the minimal form unless a frame count has
+/// to be stored too.
+#[cfg(any(test, not(feature = "no_std")))]
+pub(crate) fn encode(h: &Hdr, c: Coding, start: u8)
+    -> Result<Vec<u8>, RErr> {
+    encode_tagged(h, c, start, &[])
+}
+
+/// Same as `encode`, with a tag block if there are any `tags`.
+/// Fails with `RErr::Tags` if they don't fit its `u32` lengths.
+#[cfg(any(test, not(feature = "no_std")))]
+pub(crate) fn encode_tagged(h: &Hdr, c: Coding, start: u8,
+                            tags: &[(&str, &str)]) -> Result<Vec<u8>, RErr> {
+    if start > 0x3f { return Err(RErr::StartCode) }
+
+    let compressed = c.codec != Codec::None;
+    let srate_idx = h.sample_rate_index()?;
+
+    // checks the channel count.
+    encode_header_bits(h.format, srate_idx, h.channels, compressed)?;
+
+    let flags = if h.frames.is_some() { EXT_FRAMES } else { 0 }
+              | if c.rle { EXT_RLE } else { 0 }
+              | if c.crc { EXT_CRC } else { 0 }
+              | if c.delta { EXT_DELTA } else { 0 }
+              | if tags.is_empty() { 0 } else { EXT_TAGS };
+    let format = match flags
+    { 0 => h.format as u8, _ => FMT_EXT };
+    let mut hdr = pack(start, format, srate_idx, h.channels,
+                       compressed).to_vec();
+
+    if flags != 0 {
+        hdr.extend_from_slice(&[VERSION << 6 | h.format as u8, flags]);
+    }
+    if let Some(n) = h.frames {
+        hdr.extend_from_slice(&n.to_le_bytes());
+    }
+
+    if !tags.is_empty() {
+        let mut block = Vec::new();
+        for s in tags.iter().flat_map(|&(k, v)| [k, v]) {
+            let n = u32::try_from(s.len()).map_err(|_| RErr::Tags)?;
+            block.extend_from_slice(&n.to_le_bytes());
+            block.extend_from_slice(s.as_bytes());
+        }
+
+        let n = u32::try_from(block.len()).map_err(|_| RErr::Tags)?;
+        hdr.extend_from_slice(&n.to_le_bytes());
+        hdr.extend_from_slice(&block);
+    }
+
+    Ok(hdr)
+}
+
+/// Length of a header carrying a frame count, which footers
+/// always do.
+#[cfg(any(test, not(feature = "no_std")))]
+pub(crate) const FRAMES_HDR_LEN: usize = 12;
+
+/// Number of bytes `encode` codes `h` to, with no flags set
+/// besides the frame count.
+#[cfg(any(test, not(feature = "no_std")))]
+pub(crate) fn encoded_len(h: &Hdr) -> usize {
+    match h.frames { Some(_) => FRAMES_HDR_LEN, None => 2 }
+}
+
+/// Outcome of parsing the start of a stream.
+#[cfg_attr(all(feature = "no_std", not(test)), allow(dead_code))]
+pub(crate) enum Parsed {
+    /// Header, coding of the payload, the offset of the tag
+    /// block (the header length too when there's none, as it's
+    /// always last) and the header length.
+    Done(Hdr, Coding, usize, usize),
+    /// At least this many bytes are needed to go further.
+    Need(usize),
+}
+
+/// Parse the start of a stream beginning with `start`, with
+/// `strict` rejecting the extended headers this crate would
+/// never write.
+pub(crate) fn parse(b: &[u8], strict: bool, start: u8)
+    -> Result<Parsed, RErr> {
+    if b.len() < 2 { return Ok(Parsed::Need(2)) }
+    let (mut format, compressed, rate, channels) = fields(&[b[0], b[1]], start)?;
+
+    let mut len = 2;
+    let mut tags = 0;
+    let mut frames = None;
+    let mut c = Coding::from(match compressed {
+        true => Codec::Zstd,
+        false => Codec::None
+    });
+
+    if format == FMT_EXT {
+        if b.len() < 4 { return Ok(Parsed::Need(4)) }
+
+        // whatever a later revision changed, it can't be read.
+        if b[2] >> 6 != VERSION { return Err(RErr::Version) }
+
+        // a flag this revision doesn't know could be announcing a
+        // field, and then where the payload starts is unknown.
+        if b[3] & !EXT_KNOWN != 0 { return Err(RErr::Inconsistent) }
+
+        // the middle bits of the format byte are reserved, and an
+        // extended header without any field should be minimal.
+        if strict && (b[2] & 0x38 != 0 || b[3] == 0)
+        { return Err(RErr::Inconsistent) }
+
+        format = b[2] & 7;
+        len = 4;
+        if b[3] & EXT_FRAMES != 0 {
+            if b.len() < len + 8 { return Ok(Parsed::Need(len + 8)) }
+
+            let mut n = [0u8; 8];
+            n.copy_from_slice(&b[len..len + 8]);
+            frames = Some(u64::from_le_bytes(n));
+            len += 8;
+        }
+
+        if b[3] & EXT_CODEC != 0 {
+            if b.len() < len + 1 { return Ok(Parsed::Need(len + 1)) }
+
+            // codecs the compression flag can tell are never
+            // written with the byte.
+            c.codec = Codec::try_from(b[len])?;
+            if strict && (c.codec as u8) < 2
+            { return Err(RErr::Inconsistent) }
+
+            len += 1;
+        }
+
+        c.rle = b[3] & EXT_RLE != 0;
+        c.crc = b[3] & EXT_CRC != 0;
+        c.delta = b[3] & EXT_DELTA != 0;
+
+        if b[3] & EXT_TAGS != 0 {
+            if b.len() < len + 4 { return Ok(Parsed::Need(len + 4)) }
+
+            let n = u32::from_le_bytes([b[len], b[len + 1], b[len + 2],
+                                        b[len + 3]]) as usize;
+            len += 4;
+            tags = len;
+            if b.len() < len + n { return Ok(Parsed::Need(len + n)) }
+            len += n;
+        }
+    }
+
+    let h = Hdr { format: Fmt::try_from(format)?, rate, channels, frames };
+    Ok(Parsed::Done(h, c, if tags == 0 { len } else { tags }, len))
+}
+
+/// Set the compression flag of the header in `b`, nothing else
+/// depends on it as long as the codec is Zstd or none.
+#[cfg(feature = "compression")]
+#[cfg(any(test, not(feature = "no_std")))]
+pub(crate) fn set_compressed(b: &mut [u8], compressed: bool) {
+    b[0] = b[0] & !2 | (compressed as u8) << 1;
+}
+
+/// Replace the format code of the whole header in `b` with that
+/// of `Int8`, returning the one it had.
+#[cfg(any(test, not(feature = "no_std")))]
+pub(crate) fn take_format_code(b: &mut [u8]) -> u8 {
+    let format = (b[0] & 1) << 2 | b[1] >> 6;
+    if format == FMT_EXT {
+        let format = b[2] & 7;
+        b[2] &= !7;
+        return format
+    }
+
+    b[0] &= !1;
+    b[1] &= 0x3f;
+    format
+}
+
+/// Unpack the first two bytes of a stream beginning with
+/// `start`: the format code, compression flag, samplerate and
+/// number of channels. Const, like what builds on it below, so
+/// neither an allocator nor `std::io` can be involved.
+const fn fields(b: &[u8; 2], start: u8)
+    -> Result<(u8, bool, u32, u8), RErr> {
+    if b[0] >> 2 != start { return Err(RErr::StartCode) }
+    let rate = match rate_from_index(b[1] >> 3 & 7) {
+        Ok(r) => r,
+        Err(e) => return Err(e)
+    };
+
+    Ok(((b[0] & 1) << 2 | b[1] >> 6,
+        b[0] >> 1 & 1 == 1,
+        rate,
+        (b[1] & 7) + 1))
+}
+
+/// Parse a standard two byte header, returning it with its
+/// compression flag. Everything it touches is in `core`, so it
+/// suits targets without an allocator, and it can be evaluated
+/// in a `const` context. Extended headers (format code 7) can't
+/// be parsed from two bytes and fail with `RErr::Truncated`,
+/// `parse_header` reads those.
+pub const fn parse_header_minimal(bytes: &[u8; 2]) -> Result<(Hdr, bool), RErr> {
+    let (format, compressed, rate, channels) = match fields(bytes, START_CODE) {
+        Ok(f) => f,
+        Err(e) => return Err(e)
+    };
+
+    if format == FMT_EXT { return Err(RErr::Truncated) }
+    match Fmt::from_code(format) {
+        Some(format) => Ok((Hdr { format, rate, channels, frames: None },
+                            compressed)),
+        None => Err(RErr::Format)
+    }
+}
+
+/// Parse the header at the start of `bytes`, returning it with
+/// the codec of the payload and the offset the payload starts
+/// at. Fails with `RErr::Truncated` if `bytes` ends too early.
+/// Parsing is lenient, as with `demux`.
+pub fn parse_header(bytes: &[u8]) -> Result<(Hdr, Codec, usize), RErr> {
+    parse_complete(bytes, START_CODE).map(|(h, c, len)| (h, c.codec, len))
+}
+
+/// Same as `parse_header` with the whole coding, for streams
+/// beginning with `start`.
+pub(crate) fn parse_complete(bytes: &[u8], start: u8)
+    -> Result<(Hdr, Coding, usize), RErr> {
+    match parse(bytes, false, start)? {
+        Parsed::Done(h, c, _, len) => Ok((h, c, len)),
+        Parsed::Need(_) => Err(RErr::Truncated)
+    }
+}
+
+/// Spell out the bit fields at the start of `bytes` one by one,
+/// for looking into malformed streams, e.g. for the default
+/// header: `startcode=0x3d compressed=false fmt=Int16
+/// rate_idx=4(44100) ch=2`. An extended header adds its version,
+/// real format code and flags. Nothing is validated, reserved
+/// codes are shown as they are.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn debug_header(bytes: &[u8]) -> String {
+    if bytes.len() < 2 {
+        return format!("truncated, {} of 2 bytes", bytes.len())
+    }
+
+    let fmt_name = |f: u8| match Fmt::from_code(f) {
+        Some(f) => format!("{:?}", f),
+        None => format!("{}(reserved)", f)
+    };
+
+    let (b0, b1) = (bytes[0], bytes[1]);
+    let fmt = (b0 & 1) << 2 | b1 >> 6;
+    let idx = b1 >> 3 & 7;
+    let rate = match rate_from_index(idx) {
+        Ok(r) => r.to_string(),
+        Err(_) => "reserved".into()
+    };
+    let mut s = format!("startcode={:#04x} compressed={} fmt={} \
+                         rate_idx={}({}) ch={}",
+                        b0 >> 2, b0 >> 1 & 1 == 1,
+                        if fmt == FMT_EXT { "ext".into() } else { fmt_name(fmt) },
+                        idx, rate, (b1 & 7) + 1);
+
+    if fmt == FMT_EXT {
+        match bytes.get(2..4) {
+            Some(&[f, flags]) => s += &format!(" version={} fmt={} flags={:#04x}",
+                                              f >> 6, fmt_name(f & 7), flags),
+            _ => s += " ext=truncated"
+        }
+    }
+
+    s
+}
+
+/// Decode the entries of a tag block, failing with `RErr::Tags`
+/// if they overrun it or aren't UTF-8.
+#[cfg(any(test, not(feature = "no_std")))]
+pub(crate) fn decode_tags(mut b: &[u8]) -> Result<Vec<(String, String)>, RErr> {
+    let string = |b: &mut &[u8]| {
+        if b.len() < 4 { return Err(RErr::Tags) }
+        let n = u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize;
+        if b.len() - 4 < n { return Err(RErr::Tags) }
+
+        let s = std::str::from_utf8(&b[4..4 + n]).map_err(|_| RErr::Tags)?;
+        *b = &b[4 + n..];
+        Ok(s.to_string())
+    };
+
+    let mut tags = Vec::new();
+    while !b.is_empty() {
+        let k = string(&mut b)?;
+        tags.push((k, string(&mut b)?));
+    }
+
+    Ok(tags)
+}
+
+#[test]
+fn start_code_byte() {
+    assert!(check_start_code(0xf4) && check_start_code(0xf7));
+    assert!(!check_start_code(0xf3) && !check_start_code(0xf8));
+    assert!(!check_start_code(0) && !check_start_code(b'R'));
+}
+
+#[test]
+fn srate_bounds() {
+    assert!(rate_from_index(4).unwrap() == 44100);
+    assert!(rate_from_index(7).unwrap() == 192000);
+    assert!(matches!(rate_from_index(8), Err(RErr::Rate)));
+
+    let h = Hdr::default();
+    assert!(h.sample_rate_index().unwrap() == 4);
+    assert!(rate_from_index(h.sample_rate_index().unwrap()).unwrap() == h.rate);
+    assert!(matches!(Hdr { rate: 48000, ..h }.sample_rate_index(),
+                     Err(RErr::Rate)));
+}
+
+#[test]
+fn parse_header_from_slice() {
+    use crate::encode_to_vec;
+
+    let mut h = Hdr { format: Fmt::Int16, rate: 192000, channels: 1,
+                      frames: None };
+    let file = encode_to_vec(h, &[1, 2, 3, 4], None).unwrap();
+    let (ohdr, codec, off) = parse_header(&file).unwrap();
+    assert!(ohdr == h && codec == Codec::None && off == 2);
+    assert!(file[off..] == [1, 2, 3, 4]);
+
+    h.frames = Some(2);
+    let file = encode_to_vec(h, &[1, 2, 3, 4], None).unwrap();
+    assert!(parse_header(&file).unwrap() == (h, Codec::None, 12));
+    assert!(matches!(parse_header(&file[..5]), Err(RErr::Truncated)));
+}
+
+#[test]
+fn inconsistent_ext_header() {
+    use std::io::Cursor;
+    use crate::{demux_with_options, DemuxOptions, Strictness};
+
+    let h = Hdr { format: Fmt::Int8, rate: 8000, channels: 1,
+                  frames: Some(3) };
+    let mut file = encode(&h, Coding::default(), START_CODE).unwrap();
+    file[2] |= 0x08;
+
+    let lenient = DemuxOptions::default();
+    let strict = DemuxOptions { strictness: Strictness::Strict,
+                                ..Default::default() };
+
+    let rkin = demux_with_options(Cursor::new(&file), lenient).unwrap();
+    assert!(*rkin.header() == h);
+    assert!(matches!(demux_with_options(Cursor::new(&file), strict),
+                     Err(RErr::Inconsistent)));
+
+    // a flag no revision defines yet, whatever the strictness.
+    file[2] &= !0x08;
+    file[3] |= 0x80;
+    for opts in [lenient, strict].iter() {
+        assert!(matches!(demux_with_options(Cursor::new(&file), *opts),
+                         Err(RErr::Inconsistent)));
+    }
+    assert!(matches!(parse_header(&file), Err(RErr::Inconsistent)));
+
+    // extended header with no fields in it.
+    let file = [file[0], file[1], 0, 0];
+    assert!(demux_with_options(Cursor::new(&file), lenient).is_ok());
+    assert!(matches!(demux_with_options(Cursor::new(&file), strict),
+                     Err(RErr::Inconsistent)));
+}
+
+#[test]
+fn channels_range() {
+    let h = |channels| Hdr { channels, ..Default::default() };
+
+    assert!(matches!(encode(&h(0), Coding::default(), START_CODE),
+                     Err(RErr::Channels)));
+    assert!(matches!(encode(&h(9), Coding::default(), START_CODE),
+                     Err(RErr::Channels)));
+
+    for channels in 1..=8 {
+        let b = encode(&h(channels), Coding::default(), START_CODE).unwrap();
+        assert!(parse_header(&b).unwrap().0.channels == channels);
+    }
+}
+
+#[test]
+fn format_version() {
+    let h = Hdr { format: Fmt::Float32, rate: 32000, channels: 2,
+                  frames: Some(10) };
+    let mut file = encode(&h, Coding::default(), START_CODE).unwrap();
+    assert!(file[2] >> 6 == 0);
+    assert!(parse_header(&file).unwrap().0 == h);
+
+    file[2] |= 1 << 6;
+    assert!(matches!(parse_header(&file), Err(RErr::Version)));
+}
+
+#[test]
+fn header_bits_match_mux() {
+    use crate::mux;
+
+    let cases = [(Fmt::Int8, 0, 1, false), (Fmt::Int16, 4, 2, true),
+                 (Fmt::Float32, 6, 6, false), (Fmt::Float64, 7, 8, true)];
+
+    for &(format, idx, channels, compressed) in &cases {
+        let h = Hdr { format, rate: SAMPLERATES[idx as usize], channels,
+                      frames: None };
+        let compressed = compressed && cfg!(feature = "compression");
+        let lev = if compressed { Some(1) } else { None };
+
+        let file = mux(Vec::new(), h, lev).unwrap().finish().unwrap();
+        let bits = encode_header_bits(format, idx, channels, compressed);
+        assert!(file[..2] == bits.unwrap());
+    }
+
+    assert!(encode_header_bits(Fmt::Int16, 4, 2, false).unwrap()
+            == [0xf4, 0x61]);
+    assert!(matches!(encode_header_bits(Fmt::Int8, 8, 1, false),
+                     Err(RErr::Rate)));
+    assert!(matches!(encode_header_bits(Fmt::Int8, 0, 9, false),
+                     Err(RErr::Channels)));
+}
+
+#[test]
+fn debug_header_fields() {
+    let h = Hdr::default();
+    let b = encode(&h, Codec::Zstd.into(), START_CODE).unwrap();
+    assert!(debug_header(&b) == "startcode=0x3d compressed=true fmt=Int16 \
+                                 rate_idx=4(44100) ch=2");
+
+    let b = encode(&Hdr { frames: Some(1), ..h }, Coding::default(),
+                   START_CODE).unwrap();
+    assert!(debug_header(&b[..3]).ends_with("fmt=ext rate_idx=4(44100) ch=2 \
+                                              ext=truncated"));
+    assert!(debug_header(&b).ends_with("version=0 fmt=Int16 flags=0x01"));
+    assert!(debug_header(&[0xf5]) == "truncated, 1 of 2 bytes");
+}
+
+#[test]
+fn minimal_header_in_const() {
+    const H: Result<(Hdr, bool), RErr> = parse_header_minimal(&[0xf6, 0x51]);
+
+    let h = Hdr { format: Fmt::Int16, rate: 22050, channels: 2, frames: None };
+    assert!(matches!(H, Ok((x, true)) if x == h));
+    assert!(encode_header_bits(Fmt::Int16, 2, 2, true).unwrap() == [0xf6, 0x51]);
+
+    assert!(matches!(parse_header_minimal(&[0xf5, 0xc0]), Err(RErr::Truncated)));
+    assert!(matches!(parse_header_minimal(&[0xf5, 0x80]), Err(RErr::Format)));
+    assert!(matches!(parse_header_minimal(&[0x00, 0x00]), Err(RErr::StartCode)));
+}
@@ -0,0 +1,146 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::RErr;
+
+/// Magic number of the Zstd skippable frame the index is stored
+/// in, any decoder steps over it like over padding.
+const SKIPPABLE_MAGIC: u32 = 0x184d_2a5e;
+
+/// Last bytes of the index footer, after the number of points.
+const FOOTER_TAG: [u8; 4] = *b"RKIX";
+
+/// Where the Zstd frames of a compressed payload start, so a
+/// reader can jump to the one holding a sample and decode from
+/// there (see `RkReader::seek_to_sample`). `RkWriter` builds it
+/// as `flush_frame` ends frames.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SeekIndex {
+    /// Offsets in the decoded PCM data and in the payload of
+    /// every frame but the first, in order.
+    points: Vec<(u64, u64)>,
+}
+
+impl SeekIndex {
+    /// Start of every frame past the first, as offsets in bytes
+    /// into the decoded PCM data and into the payload. A frame
+    /// needn't start on a PCM frame boundary, as `flush_frame`
+    /// can be called between any two writes.
+    pub fn points(&self) -> &[(u64, u64)] {
+        &self.points
+    }
+
+    #[cfg_attr(not(feature = "compression"), allow(dead_code))]
+    pub(crate) fn push(&mut self, pcm: u64, payload: u64) {
+        self.points.push((pcm, payload))
+    }
+
+    /// Index as a Zstd skippable frame going at the end of the
+    /// payload: the points as pairs of `u64` LE after the frame's
+    /// header, then their number as a `u32` LE and `FOOTER_TAG`.
+    #[cfg_attr(not(feature = "compression"), allow(dead_code))]
+    pub(crate) fn to_footer(&self) -> Vec<u8> {
+        let size = self.points.len() * 16 + 8;
+        let mut b = Vec::with_capacity(size + 8);
+        b.extend_from_slice(&SKIPPABLE_MAGIC.to_le_bytes());
+        b.extend_from_slice(&(size as u32).to_le_bytes());
+
+        for &(pcm, payload) in &self.points {
+            b.extend_from_slice(&pcm.to_le_bytes());
+            b.extend_from_slice(&payload.to_le_bytes());
+        }
+
+        b.extend_from_slice(&(self.points.len() as u32).to_le_bytes());
+        b.extend_from_slice(&FOOTER_TAG);
+        b
+    }
+
+    /// Read the index written by `to_footer` off the end of `r`,
+    /// for a payload starting at `from`. Anything that doesn't
+    /// add up to an index, such as a stream without one, gives
+    /// `None`, only failing to seek or read is an error.
+    pub(crate) fn read_footer<R: Read + Seek>(r: &mut R, from: u64)
+        -> Result<Option<SeekIndex>, RErr> {
+        let end = r.seek(SeekFrom::End(0)).map_err(|_| RErr::IO)?;
+        if end < from.saturating_add(16) { return Ok(None) }
+
+        let mut tail = [0u8; 8];
+        r.seek(SeekFrom::End(-8)).map_err(|_| RErr::IO)?;
+        r.read_exact(&mut tail)?;
+        if tail[4..] != FOOTER_TAG { return Ok(None) }
+
+        let n = u32::from_le_bytes([tail[0], tail[1], tail[2], tail[3]]) as u64;
+        let len = n * 16 + 16;
+        if end - from < len { return Ok(None) }
+
+        let mut b = vec![0u8; len as usize - 8];
+        r.seek(SeekFrom::End(-(len as i64))).map_err(|_| RErr::IO)?;
+        r.read_exact(&mut b)?;
+
+        let u32_at = |i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2],
+                                                    b[i + 3]]);
+        if u32_at(0) != SKIPPABLE_MAGIC || u32_at(4) as u64 != len - 8 {
+            return Ok(None)
+        }
+
+        let u64_at = |i: usize| {
+            let mut x = [0u8; 8];
+            x.copy_from_slice(&b[i..i + 8]);
+            u64::from_le_bytes(x)
+        };
+        let points: Vec<_> = (0..n as usize)
+            .map(|i| (u64_at(8 + i * 16), u64_at(16 + i * 16)))
+            .collect();
+
+        // lookups need the points in order, all inside the payload.
+        let sorted = points.windows(2).all(|w| w[0].0 <= w[1].0);
+        if !sorted || points.iter().any(|p| p.1 > end - from - len) {
+            return Ok(None)
+        }
+
+        Ok(Some(SeekIndex { points }))
+    }
+
+    /// Start of the last frame beginning at or before `pcm`.
+    pub(crate) fn lookup(&self, pcm: u64) -> (u64, u64) {
+        let i = self.points.partition_point(|&(p, _)| p <= pcm);
+        match i {
+            0 => (0, 0),
+            _ => self.points[i - 1]
+        }
+    }
+}
+
+#[test]
+fn lookup_frame_start() {
+    let mut idx = SeekIndex::default();
+    assert!(idx.lookup(100) == (0, 0));
+
+    idx.push(4000, 310);
+    idx.push(8000, 650);
+    assert!(idx.lookup(3999) == (0, 0));
+    assert!(idx.lookup(4000) == (4000, 310));
+    assert!(idx.lookup(u64::MAX) == (8000, 650));
+}
+
+#[test]
+fn footer_roundtrip() {
+    use std::io::Cursor;
+
+    let mut idx = SeekIndex::default();
+    idx.push(4000, 310);
+    idx.push(8000, 650);
+
+    let mut file = vec![0u8; 1000];
+    file.extend_from_slice(&idx.to_footer());
+    assert!(file.len() == 1000 + 16 + 2 * 16);
+    let mut r = Cursor::new(file.clone());
+    assert!(SeekIndex::read_footer(&mut r, 0).unwrap() == Some(idx.clone()));
+
+    // frames past the end of the payload can't be right.
+    assert!(SeekIndex::read_footer(&mut r, 500).unwrap().is_none());
+
+    let n = file.len();
+    file[n - 1] ^= 1;
+    let mut r = Cursor::new(file);
+    assert!(SeekIndex::read_footer(&mut r, 0).unwrap().is_none());
+}
@@ -6,103 +6,965 @@
 //! This can both mux and demux the header data, from
 //! file objects and has a simple interface.
 //! 
+//! With the `no_std` feature the crate builds without `std`,
+//! for targets that only have `core`. All that's left then is
+//! the parsing of headers (`parse_header_minimal`,
+//! `parse_header`, `probe`) and the types they return, every
+//! reader and writer needs `std::io`.
+//!
 //! # Example
 //! ```
-//! use std::io::Cursor;
+//! # #[cfg(not(feature = "no_std"))] {
+//! use std::io::{Cursor, Write};
+//! use rkpi2::{mux, Fmt, Hdr};
 //!
-//! fn main() {
-//!     let out = Cursor::new(Vec::new());
-//!     let mut rkout = mux(Box::new(out),
-//!         Hdr {
-//!             format: Fmt::Int8,
-//!             rate: 8000,
-//!             channels: 1
-//!         }, None).unwrap();
-//!     rkout.write_all(vec![0u8; 8000].as_slice()).unwrap();
-//! }
+//! let out = Cursor::new(Vec::new());
+//! let mut rkout = mux(Box::new(out),
+//!     Hdr {
+//!         format: Fmt::Int8,
+//!         rate: 8000,
+//!         channels: 1,
+//!         frames: None
+//!     }, None).unwrap();
+//! rkout.write_all(vec![0u8; 8000].as_slice()).unwrap();
+//! rkout.finish().unwrap();
+//! # }
 //! ```
 
-use std::convert::TryFrom;
-use std::io::{Read, Write};
-use zstd::{Encoder, Decoder};
+#![cfg_attr(all(feature = "no_std", not(test)), no_std)]
+
+#[cfg(any(test, not(feature = "no_std")))]
+use std::fs;
+#[cfg(any(test, not(feature = "no_std")))]
+use std::io::{Cursor, Read, Seek, SeekFrom, Take, Write};
+#[cfg(any(test, not(feature = "no_std")))]
+use std::path::{Path, PathBuf};
+#[cfg(any(test, not(feature = "no_std")))]
+use header::Coding;
+use header::Parsed;
+#[cfg(any(test, not(feature = "no_std")))]
+use utils::conv::encode_f32;
 
+#[cfg(any(test, not(feature = "no_std")))]
+mod concat;
+mod header;
+#[cfg(any(test, not(feature = "no_std")))]
+mod index;
+#[cfg(all(feature = "memmap", unix, any(test, not(feature = "no_std"))))]
+mod mmap;
+#[cfg(any(test, not(feature = "no_std")))]
+mod reader;
+#[cfg(all(feature = "resample", any(test, not(feature = "no_std"))))]
+mod resample;
 mod utils;
-pub use utils::{Fmt, Hdr, RErr};
+#[cfg(any(test, not(feature = "no_std")))]
+mod wav;
+#[cfg(any(test, not(feature = "no_std")))]
+mod writer;
+pub use header::{check_start_code, encode_header_bits, parse_header,
+                 parse_header_minimal, rate_from_index};
+pub use utils::{Codec, DemuxOptions, Fmt, Hdr, MuxOptions, RErr, Strictness,
+                UnknownFormat};
+
+#[cfg(any(test, not(feature = "no_std")))]
+pub use concat::RkStreamReader;
+#[cfg(any(test, not(feature = "no_std")))]
+pub use header::debug_header;
+#[cfg(any(test, not(feature = "no_std")))]
+pub use index::SeekIndex;
+#[cfg(all(feature = "memmap", unix, any(test, not(feature = "no_std"))))]
+pub use mmap::{demux_mmap, Mmap};
+#[cfg(any(test, not(feature = "no_std")))]
+pub use reader::{ConvertingReader, RkReader};
+#[cfg(all(feature = "resample", any(test, not(feature = "no_std"))))]
+pub use resample::{mux_resampled, nearest_rate, resample_to_supported,
+                   ResamplingReader};
+#[cfg(any(test, not(feature = "no_std")))]
+pub use utils::chan::{apply_gain, extract_channel, interleave};
+#[cfg(any(test, not(feature = "no_std")))]
+pub use utils::conv::{convert, convert_dithered, DitherMode,
+                      f32_to_i8, f32_to_i8_checked,
+                      f32_to_i16, f32_to_i16_checked,
+                      f32_to_i32, f32_to_i32_checked};
+#[cfg(any(test, not(feature = "no_std")))]
+pub use utils::count::CountingWriter;
+#[cfg(any(test, not(feature = "no_std")))]
+pub use utils::ring::RingMuxBuffer;
+#[cfg(any(test, not(feature = "no_std")))]
+pub use utils::sample::Sample;
+#[cfg(any(test, not(feature = "no_std")))]
+pub use wav::{from_wav, WavReader};
+#[cfg(any(test, not(feature = "no_std")))]
+pub use writer::{ChannelMuxer, RkWriter};
+
+/// File extension of RKPI2 files, without the dot. Files are
+/// written and looked for with this one, in lower case.
+pub const EXTENSION: &str = "rkpi2";
+
+/// MIME type to serve RKPI2 streams as. The format has no
+/// registered type, so it's in the unregistered `x-` tree.
+pub const MIME_TYPE: &str = "audio/x-rkpi2";
+
+/// Whether `path` has the `EXTENSION`, in any case.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn is_rkpi2_extension<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref().extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case(EXTENSION))
+}
+
+/// How sure `probe` is that some bytes start an RKPI2 stream.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Probe {
+    /// Header parses strictly and more than its first two bytes
+    /// back it up: an extended header, or the Zstd magic number
+    /// at the start of a compressed payload.
+    Definite,
+    /// Plausible as far as it goes, e.g. a two byte header over
+    /// raw PCM, which is only a start code and a format code,
+    /// or a header cut short.
+    Maybe,
+    /// Not the start of an RKPI2 stream.
+    No
+}
+
+/// Score how likely `bytes` are the start of an RKPI2 stream,
+/// for format detection that ranks several candidates. The
+/// more of the stream there is, the surer it can be, a header
+/// and a few bytes of payload are enough.
+pub fn probe(bytes: &[u8]) -> Probe {
+    if bytes.len() < 2 {
+        return match bytes.first() {
+            Some(&b) if check_start_code(b) => Probe::Maybe,
+            _ => Probe::No
+        }
+    }
 
-const SAMPLERATES: [u32; 8] = [
-    8000, 12000, 22050, 32000, 44100,
-    64000, 96000, 192000 ];
+    let (c, len) = match header::parse(bytes, true, header::START_CODE) {
+        Ok(Parsed::Done(_, c, _, len)) => (c, len),
+        Ok(Parsed::Need(_)) | Err(RErr::Inconsistent) => return Probe::Maybe,
+        Err(_) => return Probe::No
+    };
+
+    let payload = &bytes[len..];
+    match c.codec {
+        Codec::Zstd if payload.starts_with(&ZSTD_MAGIC) => Probe::Definite,
+        Codec::Zstd if ZSTD_MAGIC.starts_with(payload) => Probe::Maybe,
+        Codec::Zstd => Probe::No,
+        Codec::None if len > 2 => Probe::Definite,
+        Codec::None => Probe::Maybe
+    }
+}
+
+/// Most bytes `decode_from_slice` allocates up-front for a
+/// compressed payload, see `DemuxOptions::max_prealloc`.
+#[cfg(any(test, not(feature = "no_std")))]
+const DEFAULT_MAX_PREALLOC: usize = 16 << 20;
+
+/// Magic number every Zstd frame starts with.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Version of the Zstd library linked in, e.g. `"1.4.5"`, to
+/// put in bug reports: compressed output is only reproducible
+/// on the same version (see `mux`).
+#[cfg(feature = "compression")]
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn zstd_version() -> &'static str {
+    zstd_safe::version_string()
+}
 
 /// Mux RKPI2 header data into a writer with a given header
 /// and compress PCM data with Zstd if a level was specified.
 /// 
-/// The level is the same as the scale of Zstd: (+1..+21].
-pub fn mux(w: Box<dyn Write>, h: Hdr, lev: Option<u8>)
-    -> Result<Box<dyn Write>, RErr> {
+/// The level is the same as the scale of Zstd: (+1..+21],
+/// asking for one without the `compression` feature fails
+/// with `RErr::CompressionUnsupported`.
+/// Call `finish` on the returned writer once all the PCM
+/// data is written, otherwise the Zstd frame is left open.
+///
+/// A header with a frame count is written in the extended
+/// form, otherwise the minimal two byte one is used.
+///
+/// Output is reproducible: the same header, PCM data, options
+/// and `flush_frame` calls give the same bytes on the same Zstd
+/// version. Zstd is only run single-threaded here, which is
+/// what keeps it deterministic, a different Zstd version may
+/// still compress differently.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn mux<W: Write>(w: W, h: Hdr, lev: Option<u8>)
+    -> Result<RkWriter<W>, RErr> {
+    mux_with_options(w, h, MuxOptions { level: lev,
+                                        ..Default::default() })
+}
+
+/// Same as `mux`, with the payload configured by `opts`.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn mux_with_options<W: Write>(w: W, h: Hdr, opts: MuxOptions)
+    -> Result<RkWriter<W>, RErr> {
+    mux_with_tags(w, h, opts, &[])
+}
+
+/// Same as `mux_with_options`, storing key/value `tags` in the
+/// header, see `read_tags`. Keys needn't be unique, and they're
+/// kept in order. Footers can't carry tags, so footer mode with
+/// any fails with `RErr::Tags`.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn mux_with_tags<W: Write>(w: W, h: Hdr, opts: MuxOptions,
+                               tags: &[(&str, &str)])
+    -> Result<RkWriter<W>, RErr> {
+    if opts.backfill_frames && !opts.footer
+    { return Err(RErr::Unseekable) }
+    if opts.footer && !tags.is_empty() { return Err(RErr::Tags) }
+
     let mut w = w;
+    let hdr = header::encode_tagged(&h, opts.coding(&h), opts.start_code(),
+                                    tags)?;
 
-    let srate_idx = match SAMPLERATES
-        .iter().position(|&s| s == h.rate) {
-        Some(S) => S as u8,
-        None => { return Err(RErr::Rate) }
-    };
+    if opts.footer { return RkWriter::new(w, h, 0, opts) }
+    if held_header(&opts) {
+        return Ok(RkWriter::new(w, h, hdr.len(), opts)?.hold_header(hdr))
+    }
+    if w.write_all(&hdr).is_err() { return Err(RErr::IO) }
+
+    RkWriter::new(w, h, hdr.len(), opts)
+}
+
+/// Whether the header waits for `auto_store` to decide on the
+/// compression flag.
+#[cfg(any(test, not(feature = "no_std")))]
+fn held_header(opts: &MuxOptions) -> bool {
+    opts.auto_store && opts.level.is_some()
+}
 
-    let channels = match h.channels {
-        (1..=8) => h.channels,
-        _ => { return Err(RErr::Channels) }
+/// Same as `mux_with_options`, over a writer that can seek, so
+/// `opts.backfill_frames` is supported. The header is written
+/// at the current position of `w`, which is checked up-front:
+/// a pipe or socket that can't tell its position fails here
+/// with `RErr::Unseekable`, rather than on `finish`.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn mux_seekable<W: Write + Seek>(w: W, h: Hdr, opts: MuxOptions)
+    -> Result<RkWriter<W>, RErr> {
+    mux_seekable_with_tags(w, h, opts, &[])
+}
+
+/// Same as `mux_seekable`, storing `tags` in the header as
+/// `mux_with_tags` does.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn mux_seekable_with_tags<W: Write + Seek>(w: W, h: Hdr, opts: MuxOptions,
+                                               tags: &[(&str, &str)])
+    -> Result<RkWriter<W>, RErr> {
+    if !opts.backfill_frames || opts.footer
+    { return mux_with_tags(w, h, opts, tags) }
+
+    let mut w = w;
+    let start = match w.stream_position() {
+        Ok(p) => p,
+        Err(_) => return Err(RErr::Unseekable)
     };
 
-    let compressed = match lev
-    { Some(_) => true, None => false };
+    let h = Hdr { frames: Some(0), ..h };
+    let hdr = header::encode_tagged(&h, opts.coding(&h), opts.start_code(),
+                                    tags)?;
 
-    if let Err(_) = w.write_all(&[
-        0x3d                 << 2|
-        (compressed as u8)   << 1|
-        (h.format as u8)     >> 2,
-        (h.format as u8 & 3) << 6|
-        srate_idx            << 3|
-        channels        - 1
-    ]) { return Err(RErr::IO) }
+    let held = held_header(&opts);
+    if !held && w.write_all(&hdr).is_err() { return Err(RErr::IO) }
 
-    match lev {
-        Some(L) => match Encoder::new(w, L as i32)
-        { Ok(C) => Ok(Box::new(C)),
-          Err(_) => Err(RErr::IO) },
-        None => Ok(w)
-    }
+    let len = hdr.len();
+    let mut rkout = RkWriter::new(w, h, len, opts)?;
+    if held { rkout = rkout.hold_header(hdr) }
+    rkout.backfill_at(start);
+    Ok(rkout)
 }
 
 /// Demux RKPI2 header data from the given reader, if compression
 /// was done wrap the reader with Zstd decompression adapter.
-pub fn demux(r: Box<dyn Read>)
-    -> Result<(Box<dyn Read>, Hdr), RErr> {
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn demux<R: Read>(r: R) -> Result<RkReader<R>, RErr> {
+    demux_with_options(r, DemuxOptions::default())
+}
+
+/// Same as `demux`, with the checks configured by `opts`.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn demux_with_options<R: Read>(r: R, opts: DemuxOptions)
+    -> Result<RkReader<R>, RErr> {
     let mut r = r;
+    let strict = opts.strictness == Strictness::Strict;
+    let passthrough = opts.on_unknown_format == UnknownFormat::Passthrough;
+    let (h, coding, _, unknown) = read_header_as(&mut r, strict, opts.start_code(),
+                                                 passthrough)?;
 
-    let mut hdr = [0u8; 2];
-    if let Err(_) = r.read(&mut hdr)
-    { return Err(RErr::IO) }
+    let mut head = Vec::new();
+    if strict {
+        head = peek(&mut r, ZSTD_MAGIC.len())?;
+        if (head == ZSTD_MAGIC) != (coding.codec == Codec::Zstd)
+        { return Err(RErr::Decompress) }
+    }
 
-    if hdr[0] >> 2 != 0x3d { Err(RErr::StartCode) }
-    else {
-        let format = match Fmt::try_from(
-            (hdr[0] & 1) << 2 | hdr[1] >> 6)
-        { Ok(F) => F, Err(E) => { return Err(E) } };
+    RkReader::new(Cursor::new(head).chain(r), h, coding, opts)
+        .map(|rkin| rkin.with_unknown_format(unknown))
+}
+
+/// Read the header off `r`, returning it with the coding of the
+/// payload and the tag block.
+#[cfg(any(test, not(feature = "no_std")))]
+fn read_header<R: Read>(r: &mut R, strict: bool, start: u8)
+    -> Result<(Hdr, Coding, Vec<u8>), RErr> {
+    read_header_as(r, strict, start, false).map(|(h, c, tags, _)| (h, c, tags))
+}
 
-        let h = Hdr {
-            format: format,
-            rate: SAMPLERATES[(hdr[1] >> 3 & 3) as usize],
-            channels: (hdr[1] & 3) + 1
+/// Same as `read_header`, with a reserved format code read as
+/// `Int8` and returned, unless `passthrough` is off.
+#[cfg(any(test, not(feature = "no_std")))]
+fn read_header_as<R: Read>(r: &mut R, strict: bool, start: u8, passthrough: bool)
+    -> Result<(Hdr, Coding, Vec<u8>, Option<u8>), RErr> {
+    let mut hdr = Vec::new();
+    let mut unknown = None;
+    loop {
+        let parsed = match header::parse(&hdr, strict, start) {
+            Err(RErr::Format) if passthrough && unknown.is_none() => {
+                unknown = Some(header::take_format_code(&mut hdr));
+                continue
+            },
+            p => p?
         };
 
-        match (hdr[0] >> 1 & 1) == 1 {
-            true => match Decoder::new(r)
-            { Ok(D) => Ok((Box::new(D), h)),
-              Err(_) => Err(RErr::IO) },
-            false => Ok((r, h))
+        match parsed {
+            Parsed::Done(h, c, tags, _) => {
+                hdr.drain(..tags);
+                return Ok((h, c, hdr, unknown))
+            },
+            Parsed::Need(n) => {
+                let more = peek(r, n - hdr.len())?;
+                if hdr.len() + more.len() < n
+                { return Err(RErr::IO) }
+
+                hdr.extend_from_slice(&more);
+            }
         }
     }
 }
 
+/// Same as `demux`, over a reader that can seek, with the
+/// `index` of the stream's Zstd frames (see `RkWriter::seek_index`)
+/// so that `RkReader::seek_to_sample` can go anywhere in it. An
+/// uncompressed stream needs no index, an empty one will do.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn demux_indexed<R: Read + Seek>(r: R, index: SeekIndex)
+    -> Result<RkReader<R>, RErr> {
+    let mut r = r;
+    let (h, coding, _) = read_header(&mut r, false, header::START_CODE)?;
+    let at = r.stream_position().map_err(|_| RErr::IO)?;
+
+    let rkin = RkReader::new(Cursor::new(Vec::new()).chain(r), h, coding,
+                             DemuxOptions::default())?;
+    Ok(rkin.with_index(at, index))
+}
+
+/// Same as `demux_with_options`, over a reader that can seek,
+/// looking for the index stored at the end of the stream (see
+/// `MuxOptions::index_interval`) for `RkReader::seek_to_sample`
+/// to use. That means seeking to the end and back, so the
+/// stream has to end with the RKPI2 one. An uncompressed payload
+/// needs no index and is seekable anyway, a compressed one
+/// without an index is read as with `demux_with_options`.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn demux_seekable<R: Read + Seek>(r: R, opts: DemuxOptions)
+    -> Result<RkReader<R>, RErr> {
+    let mut r = r;
+    let start = r.stream_position().map_err(|_| RErr::IO)?;
+    let (_, coding, _) = read_header(&mut r, false, opts.start_code())?;
+    let at = r.stream_position().map_err(|_| RErr::IO)?;
+
+    let index = match coding.codec {
+        Codec::None => Some(SeekIndex::default()),
+        Codec::Zstd => SeekIndex::read_footer(&mut r, at)?
+    };
+
+    if r.seek(SeekFrom::Start(start)).is_err() { return Err(RErr::IO) }
+    let rkin = demux_with_options(r, opts)?;
+    Ok(match index {
+        Some(index) => rkin.with_index(at, index),
+        None => rkin
+    })
+}
+
+/// Key/value tags stored in the header of the stream read from
+/// `r` (see `mux_with_tags`), in order, none if it doesn't have
+/// any. Only the header is read, nothing of the payload, so a
+/// compressed stream costs no more than an uncompressed one.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn read_tags<R: Read>(r: R) -> Result<Vec<(String, String)>, RErr> {
+    let mut r = r;
+    let (_, _, tags) = read_header(&mut r, false, header::START_CODE)?;
+    header::decode_tags(&tags)
+}
+
+/// Demux a stream muxed in footer mode (see `MuxOptions::footer`)
+/// from the end of `r`, which has to be seekable to get at the
+/// header there before going back to read the payload. The
+/// stream is taken to be all of `r`, from its start.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn demux_footer<R: Read + Seek>(r: R) -> Result<RkReader<Take<R>>, RErr> {
+    let mut r = r;
+    let len = header::FRAMES_HDR_LEN;
+
+    let end = r.seek(SeekFrom::End(0)).map_err(|_| RErr::IO)?;
+    if end < len as u64 { return Err(RErr::Truncated) }
+
+    let mut hdr = vec![0; len];
+    let read = r.seek(SeekFrom::Start(end - len as u64))
+        .and_then(|_| r.read_exact(&mut hdr))
+        .and_then(|_| r.seek(SeekFrom::Start(0)));
+    if read.is_err() { return Err(RErr::IO) }
+
+    let (h, coding, _) = header::parse_complete(&hdr, header::START_CODE)?;
+    let payload = Cursor::new(Vec::new()).chain(r.take(end - len as u64));
+    RkReader::new(payload, h, coding, DemuxOptions::default())
+}
+
+/// Demux a stream embedded as a chunk of `len` bytes, header
+/// included, in some larger protocol, returning it with the
+/// length of the header. Nothing past the chunk is read, that
+/// is left in `r` for whatever comes next, the reader just ends
+/// at the end of the chunk.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn demux_chunk<R: Read>(r: R, len: u64)
+    -> Result<(RkReader<Take<R>>, usize), RErr> {
+    let mut r = r.take(len);
+    let (h, coding, _) = read_header(&mut r, false, header::START_CODE)?;
+    let used = (len - r.limit()) as usize;
+
+    let payload = Cursor::new(Vec::new()).chain(r);
+    Ok((RkReader::new(payload, h, coding, DemuxOptions::default())?, used))
+}
+
+/// Parse the header of every file in `dir` (not those in its
+/// subdirectories) with the `EXTENSION`, collecting the result
+/// of each one rather than stopping at the first that fails,
+/// sorted by path. Only headers are checked, payloads aren't
+/// read. If `dir` itself can't be read it's the only entry,
+/// with `RErr::IO`.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn scan_dir<P: AsRef<Path>>(dir: P) -> Vec<(PathBuf, Result<Hdr, RErr>)> {
+    let dir = dir.as_ref();
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return vec![(dir.to_path_buf(), Err(RErr::IO))]
+    };
+
+    let mut out: Vec<_> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && is_rkpi2_extension(p))
+        .map(|p| {
+            let h = fs::File::open(&p).map_err(|_| RErr::IO)
+                .and_then(demux)
+                .map(|r| *r.header());
+            (p, h)
+        })
+        .collect();
+
+    out.sort_by(|a, b| a.0.cmp(&b.0));
+    out
+}
+
+/// Convert every WAV file in `in_dir` (not those in its
+/// subdirectories) into an RKPI2 file of the same name, with the
+/// `EXTENSION`, in `out_dir`, read with `from_wav` and muxed
+/// with `mux_copy`, the payload configured by `opts`. RKPI2 files
+/// there are re-muxed the same way, keeping their tags.
+/// Files are spread over a thread per core, and the results are
+/// per input file, sorted by path like `scan_dir`'s.
+///
+/// Each output is written to a temporary file in `out_dir` and
+/// renamed over the final path once it's complete, so a failure
+/// leaves no half-written file. An output that would be the input
+/// itself, as with an RKPI2 file and `in_dir` being `out_dir`,
+/// fails with `RErr::IO` and the input is left alone, as does one
+/// an earlier input already goes to, like `a.wav` after `a.rkpi2`.
+/// So does a file whose conversion panics, without taking the
+/// rest of the batch down.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn transcode_dir<P: AsRef<Path>>(in_dir: P, out_dir: P, opts: MuxOptions)
+    -> Vec<(PathBuf, Result<(), RErr>)> {
+    let in_dir = in_dir.as_ref();
+    let out_dir = out_dir.as_ref();
+    let entries = match fs::read_dir(in_dir) {
+        Ok(e) => e,
+        Err(_) => return vec![(in_dir.to_path_buf(), Err(RErr::IO))]
+    };
+
+    let is_wav = |p: &Path| p.extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("wav"));
+    let mut files: Vec<_> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && (is_rkpi2_extension(p) || is_wav(p)))
+        .collect();
+    files.sort();
+
+    let outs: Vec<_> = files.iter()
+        .map(|p| p.with_extension(EXTENSION).file_name().map(|n| out_dir.join(n)))
+        .collect();
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let threads = std::thread::available_parallelism()
+        .map_or(1, |n| n.get()).min(files.len());
+    let mut out: Vec<_> = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..threads).map(|_| s.spawn(|| {
+            let mut done = Vec::new();
+            loop {
+                let i = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let p = match files.get(i) { Some(p) => p, None => break };
+                let res = match &outs[i] {
+                    Some(to) if !outs[..i].contains(&outs[i]) =>
+                        std::panic::catch_unwind(std::panic::AssertUnwindSafe(
+                            || transcode_file(p, to, is_wav(p), opts)))
+                        .unwrap_or(Err(RErr::IO)),
+                    _ => Err(RErr::IO)
+                };
+                done.push((i, res));
+            }
+            done
+        })).collect();
+
+        workers.into_iter().flat_map(|t| t.join().unwrap_or_default()).collect()
+    });
+
+    out.sort_by_key(|r| r.0);
+    out.into_iter().map(|(i, r)| (files[i].clone(), r)).collect()
+}
+
+#[cfg(any(test, not(feature = "no_std")))]
+fn transcode_file(p: &Path, to: &Path, wav: bool, opts: MuxOptions)
+    -> Result<(), RErr> {
+    let same = match (fs::canonicalize(p), fs::canonicalize(to)) {
+        (Ok(a), Ok(b)) => a == b,
+        (Err(_), _) => return Err(RErr::IO),
+        (Ok(_), Err(_)) => false
+    };
+    if same { return Err(RErr::IO) }
+
+    let src = std::io::BufReader::new(fs::File::open(p).map_err(|_| RErr::IO)?);
+    if wav {
+        let wav = from_wav(src)?;
+        let h = *wav.header();
+        return mux_file(wav, h, &[], to, opts)
+    }
+
+    let tags = read_tags(fs::File::open(p).map_err(|_| RErr::IO)?)?;
+    let tags: Vec<_> = tags.iter().map(|(k, v)| (&k[..], &v[..])).collect();
+    let rkin = demux(src)?;
+    let h = *rkin.header();
+    mux_file(rkin, h, &tags, to, opts)
+}
+
+/// Mux all of `r` into a temporary file next to `to`, renamed
+/// to it once it's complete.
+#[cfg(any(test, not(feature = "no_std")))]
+fn mux_file<R: Read>(r: R, h: Hdr, tags: &[(&str, &str)], to: &Path,
+                     opts: MuxOptions) -> Result<(), RErr> {
+    let name = to.file_name().ok_or(RErr::IO)?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(name);
+    tmp_name.push(".tmp");
+    let tmp = to.with_file_name(tmp_name);
+
+    let res = fs::File::create(&tmp).map_err(|_| RErr::IO)
+        .and_then(|dst| mux_seekable_with_tags(dst, h, opts, tags))
+        .and_then(|rkout| mux_copy(r, rkout))
+        .and_then(|_| fs::rename(&tmp, to).map_err(|_| RErr::IO));
+
+    if res.is_err() { let _ = fs::remove_file(&tmp); }
+    res
+}
+
+/// Write all of `r` to `rkout` as its PCM data, up to the EOF,
+/// and `finish` it, returning the underlying writer. Errors
+/// coming from `r` keep their `RErr`, as with a corrupt stream
+/// being read through an `RkReader`.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn mux_copy<R: Read, W: Write>(r: R, rkout: RkWriter<W>)
+    -> Result<W, RErr> {
+    let (mut r, mut rkout) = (r, rkout);
+    if let Err(e) = std::io::copy(&mut r, &mut rkout) {
+        rkout.abandon();
+        return Err(e.into())
+    }
+
+    rkout.finish()
+}
+
+/// Mux a whole PCM buffer into a new `Vec`, see `mux`.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn encode_to_vec(h: Hdr, pcm: &[u8], lev: Option<u8>)
+    -> Result<Vec<u8>, RErr> {
+    let mut out = Vec::new();
+    encode_into_vec(h, pcm, lev, &mut out)?;
+    Ok(out)
+}
+
+/// Same as `encode_to_vec`, into `out`, which is cleared first
+/// and keeps its allocation, so one buffer can be reused over a
+/// batch. Room for `max_encoded_size` is reserved up-front.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn encode_into_vec(h: Hdr, pcm: &[u8], lev: Option<u8>, out: &mut Vec<u8>)
+    -> Result<(), RErr> {
+    out.clear();
+    let frames = (pcm.len() as u64).div_ceil(h.block_align().max(1) as u64);
+    out.reserve(max_encoded_size(&h, frames, lev) as usize);
+
+    let mut rkout = mux(&mut *out, h, lev)?;
+    if rkout.write_all(pcm).is_err() { return Err(RErr::IO) }
+
+    rkout.finish().map(|_| ())
+}
+
+/// Upper bound on the size of a stream of `frames` frames
+/// muxed with `h` and `lev` by `mux`, header included, for
+/// sizing output buffers ahead of encoding. That's all it
+/// covers: the other `MuxOptions` at their defaults, no tags
+/// and no `flush_frame`. A `pcm_crc` trailer, `silence_rle`
+/// records, the extra Zstd frames of `flush_frame` and
+/// `index_interval` and the index footer all come on top. The
+/// Zstd bound is the one of `ZSTD_compressBound`, for a single
+/// frame with no checksum, add 4 bytes per frame for
+/// `zstd_checksum`.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn max_encoded_size(h: &Hdr, frames: u64, lev: Option<u8>) -> u64 {
+    let raw = frames.saturating_mul(h.block_align() as u64);
+    let payload = match lev {
+        Some(_) => raw.saturating_add(raw >> 8)
+                      .saturating_add((128u64 << 10).saturating_sub(raw) >> 11),
+        None => raw
+    };
+
+    payload.saturating_add(header::encoded_len(h) as u64)
+}
+
+/// Bytes of PCM data `estimate_ratio` compresses at a time.
+#[cfg(feature = "compression")]
+#[cfg(any(test, not(feature = "no_std")))]
+pub(crate) const RATIO_SAMPLE: usize = 1 << 20;
+
+/// Rough ratio of the size of `pcm` to its size compressed at
+/// `level`, e.g. `3.0` for a third, from compressing only its
+/// first and middle megabyte. Audio changing character past
+/// those, or a payload repeating over distances a sample can't
+/// see, throw the estimate off, but telling silence or noise
+/// from music will do. Empty `pcm` gives `1.0`.
+#[cfg(feature = "compression")]
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn estimate_ratio(pcm: &[u8], level: u8) -> f64 {
+    let n = pcm.len();
+    let mid = (n / 2).saturating_sub(RATIO_SAMPLE / 2).max(RATIO_SAMPLE.min(n));
+    let parts = [&pcm[..RATIO_SAMPLE.min(n)],
+                 &pcm[mid..(mid + RATIO_SAMPLE).min(n)]];
+
+    let mut z = vec![0u8; zstd_safe::compress_bound(RATIO_SAMPLE)];
+    let (mut raw, mut packed) = (0, 0);
+    for p in parts.iter().filter(|p| !p.is_empty()) {
+        raw += p.len();
+        packed += zstd_safe::compress(&mut z, p, level as i32).unwrap_or(p.len());
+    }
+
+    if packed == 0 { return 1.0 }
+    raw as f64 / packed as f64
+}
+
+/// Demux a whole RKPI2 file held in memory, returning its
+/// header and decoded PCM data. The output is allocated
+/// up-front when the size is known from the frame count or
+/// the Zstd frame header.
+///
+/// Fails with `RErr::Truncated` if the payload holds fewer
+/// frames than the header's frame count.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn decode_from_slice(bytes: &[u8]) -> Result<(Hdr, Vec<u8>), RErr> {
+    decode_from_slice_with_options(bytes, DemuxOptions::default())
+}
+
+/// Same as `decode_from_slice`, demuxing as configured by `opts`.
+///
+/// The sizes claimed by the stream are never trusted for more
+/// than the payload could hold: an uncompressed one as many
+/// bytes as there are left, a compressed or silence-coded one
+/// no more than `opts.max_prealloc`. Past that, the output grows
+/// as usual.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn decode_from_slice_with_options(bytes: &[u8], opts: DemuxOptions)
+    -> Result<(Hdr, Vec<u8>), RErr> {
+    let (_, coding, off) = header::parse_complete(bytes, opts.start_code())?;
+    let compressed = coding.codec != Codec::None;
+    let mut rkin = demux_with_options(bytes, opts)?;
+
+    let hint = rkin.decoded_size_hint()
+        .or_else(|| content_size(&bytes[off..], compressed && !coding.rle));
+    let cap = match compressed || coding.rle {
+        true => opts.max_prealloc.unwrap_or(DEFAULT_MAX_PREALLOC),
+        false => bytes.len() - off
+    };
+
+    let len = hint.unwrap_or(0).min(cap as u64);
+    let mut pcm = Vec::with_capacity(len as usize);
+    rkin.read_to_end(&mut pcm).map_err(|e| rkin.rerr(e))?;
+
+    let h = *rkin.header();
+    match rkin.decoded_size_hint() {
+        Some(n) if (pcm.len() as u64) < n => Err(RErr::Truncated),
+        _ => Ok((h, pcm))
+    }
+}
+
+/// Longest a Zstd frame header can get, magic number included.
+#[cfg(any(test, not(feature = "no_std")))]
+const ZSTD_FRAMEHEADER_MAX: usize = 18;
+
+/// Number of frames in the stream read from `r`, from the frame
+/// count in its header or else the decoded size the Zstd frame
+/// header records, nothing of the payload is decoded. That size
+/// is only written by one-shot compressors, not by `mux`, and
+/// counts the first Zstd frame alone; it isn't used for silence
+/// coded payloads, whose decoded size means nothing. `None` when
+/// neither is there.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn frame_count<R: Read>(r: R) -> Result<Option<u64>, RErr> {
+    let mut r = r;
+    let (h, coding, _) = read_header(&mut r, false, header::START_CODE)?;
+    if h.frames.is_some() { return Ok(h.frames) }
+    if coding.codec != Codec::Zstd || coding.rle { return Ok(None) }
+
+    let mut head = [0u8; ZSTD_FRAMEHEADER_MAX];
+    let n = reader::fill(&mut r, &mut head).map_err(RErr::from)?;
+    Ok(content_size(&head[..n], true).map(|n| h.frames_in(n)))
+}
+
+/// Decoded size recorded in the Zstd frame header, if any.
+#[cfg(feature = "compression")]
+#[cfg(any(test, not(feature = "no_std")))]
+fn content_size(payload: &[u8], compressed: bool) -> Option<u64> {
+    if !compressed { return None }
+
+    match zstd_safe::get_frame_content_size(payload) {
+        zstd_safe::CONTENTSIZE_UNKNOWN
+        | zstd_safe::CONTENTSIZE_ERROR => None,
+        n => Some(n)
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+#[cfg(any(test, not(feature = "no_std")))]
+fn content_size(_: &[u8], _: bool) -> Option<u64> { None }
+
+/// Mux interleaved float samples pulled from an iterator,
+/// converting them to `h.format` as they come, and finish the
+/// stream. Returns the number of frames written.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn mux_from_iter<W, I>(w: W, h: Hdr, lev: Option<u8>, iter: I)
+    -> Result<u64, RErr>
+    where W: Write, I: IntoIterator<Item = f32> {
+    let mut rkout = mux(w, h, lev)?;
+    let mut iter = iter.into_iter().peekable();
+    let (mut chunk, mut out) = (Vec::new(), Vec::new());
+    let mut samples = 0u64;
+
+    while iter.peek().is_some() {
+        chunk.clear();
+        chunk.extend(iter.by_ref().take(4096));
+        samples += chunk.len() as u64;
+
+        out.clear();
+        encode_f32(&chunk, h.format, &mut out);
+        if rkout.write_all(&out).is_err() { return Err(RErr::IO) }
+    }
+
+    rkout.finish()?;
+    Ok(samples / h.channels as u64)
+}
+
+/// Mux one float buffer per channel, interleaving them and
+/// converting to `h.format`, then finish the stream. There
+/// must be exactly `h.channels` buffers, and they must all be
+/// the same length, otherwise `RErr::Channels` is returned.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn mux_planar<W: Write>(w: W, h: Hdr, planar: &[&[f32]], lev: Option<u8>)
+    -> Result<(), RErr> {
+    if planar.len() != h.channels as usize { return Err(RErr::Channels) }
+
+    let mut out = Vec::new();
+    encode_f32(&interleave(planar)?, h.format, &mut out);
+
+    let mut rkout = mux(w, h, lev)?;
+    if rkout.write_all(&out).is_err() { return Err(RErr::IO) }
+
+    rkout.finish()?;
+    Ok(())
+}
+
+/// Mux two mono streams into one stereo stream, `left` in the
+/// first channel and `right` in the second, both demuxed as
+/// with `demux`. They must be mono (or fail with `RErr::Channels`),
+/// both at `rate` (or fail with `RErr::Mismatch`) and agree on
+/// the sampleformat (`RErr::Format`).
+///
+/// `pad` is the option for inputs of different lengths: if set,
+/// the shorter one is padded with silence, otherwise that fails
+/// with `RErr::Truncated` once the longer one is found to go on.
+/// An input ending partway through a sample fails with
+/// `RErr::Misaligned` rather than losing what's left of it.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn merge_mono_to_stereo<L: Read, R: Read, W: Write>(left: L, right: R,
+                                                       rate: u32,
+                                                       lev: Option<u8>,
+                                                       pad: bool, w: W)
+    -> Result<(), RErr> {
+    let (mut l, mut r) = (demux(left)?, demux(right)?);
+    let (lh, rh) = (*l.header(), *r.header());
+    if lh.channels != 1 || rh.channels != 1 { return Err(RErr::Channels) }
+    if lh.rate != rate || rh.rate != rate { return Err(RErr::Mismatch) }
+    if lh.format != rh.format { return Err(RErr::Format) }
+
+    let mut rkout = mux(w, Hdr { channels: 2, frames: None, ..lh }, lev)?;
+    match interleave_mono(&mut l, &mut r, pad, &mut rkout) {
+        Ok(()) => rkout.finish().map(|_| ()),
+        Err(e) => { rkout.abandon(); Err(e) }
+    }
+}
+
+/// The samples of `l` and `r` in turn, see `merge_mono_to_stereo`.
+#[cfg(any(test, not(feature = "no_std")))]
+fn interleave_mono<L: Read, R: Read, W: Write>(l: &mut RkReader<L>,
+                                               r: &mut RkReader<R>, pad: bool,
+                                               rkout: &mut RkWriter<W>)
+    -> Result<(), RErr> {
+    let s = l.header().format.size();
+    let (mut lb, mut rb) = (vec![0u8; s << 12], vec![0u8; s << 12]);
+    let mut out = Vec::with_capacity(s << 13);
+
+    loop {
+        let ln = reader::fill(l, &mut lb).map_err(RErr::from)?;
+        let rn = reader::fill(r, &mut rb).map_err(RErr::from)?;
+        if !ln.is_multiple_of(s) || !rn.is_multiple_of(s) {
+            return Err(RErr::Misaligned)
+        }
+        if ln == 0 && rn == 0 { return Ok(()) }
+        if ln != rn && !pad { return Err(RErr::Truncated) }
+
+        let n = ln.max(rn);
+        lb[ln..n].iter_mut().for_each(|b| *b = 0);
+        rb[rn..n].iter_mut().for_each(|b| *b = 0);
+
+        out.clear();
+        for (a, b) in lb[..n].chunks_exact(s).zip(rb[..n].chunks_exact(s)) {
+            out.extend_from_slice(a);
+            out.extend_from_slice(b);
+        }
+        if rkout.write_all(&out).is_err() { return Err(RErr::IO) }
+    }
+}
+
+/// Write just the header, flagging the payload as compressed
+/// or not, and return how many bytes it took. What follows is
+/// up to the caller: raw PCM data, or a Zstd stream of it when
+/// `compressed`, written with `write_pcm_only` or any other way.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn write_header<W: Write>(w: &mut W, h: Hdr, compressed: bool)
+    -> Result<usize, RErr> {
+    let codec = if compressed { Codec::Zstd } else { Codec::None };
+    let b = header::encode(&h, codec.into(), header::START_CODE)?;
+    if w.write_all(&b).is_err() { return Err(RErr::IO) }
+
+    Ok(b.len())
+}
+
+/// Write raw PCM data with no header in front of it, for use
+/// inside containers carrying the header out-of-band. Such a
+/// stream isn't self-describing, the header has to be kept
+/// around to make any sense of it again.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn write_pcm_only<W: Write>(w: W, samples: &[u8]) -> Result<(), RErr> {
+    let mut w = w;
+    w.write_all(samples).map_err(|_| RErr::IO)
+}
+
+/// Headerless counterpart of `mux`: the typed writers of the
+/// returned sink are checked against `h`, which is never
+/// written. The payload is left uncompressed.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn mux_pcm_only<W: Write>(w: W, h: Hdr) -> Result<RkWriter<W>, RErr> {
+    RkWriter::new(w, h, 0, MuxOptions::default())
+}
+
+/// Read a headerless stream written by `write_pcm_only` or
+/// `mux_pcm_only`, with its header supplied by the caller.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn read_pcm_only<R: Read>(r: R, h: Hdr) -> Result<RkReader<R>, RErr> {
+    demux_with_header(r, h, Codec::None)
+}
+
+/// Read a payload whose header is kept out-of-band, such as
+/// in a sidecar file, decoding it with `codec`. Nothing is
+/// parsed from `r`, it's all PCM data (or a Zstd stream of it).
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn demux_with_header<R: Read>(r: R, h: Hdr, codec: Codec)
+    -> Result<RkReader<R>, RErr> {
+    RkReader::new(Cursor::new(Vec::new()).chain(r), h, codec.into(),
+                  DemuxOptions::default())
+}
+
+/// Re-mux an RKPI2 stream with a different compression level,
+/// or none at all, keeping its header, tags and PCM data as is.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn transcode<R: Read, W: Write>(src: R, dst: W, new_level: Option<u8>)
+    -> Result<Hdr, RErr> {
+    let mut src = src;
+    let (h, coding, tags) = read_header(&mut src, false, header::START_CODE)?;
+
+    let tags = header::decode_tags(&tags)?;
+    let tags: Vec<(&str, &str)> = tags.iter()
+        .map(|(k, v)| (k.as_str(), v.as_str())).collect();
+
+    let mut rkin = RkReader::new(Cursor::new(Vec::new()).chain(src), h, coding,
+                                 DemuxOptions::default())?;
+    let opts = MuxOptions { level: new_level, ..Default::default() };
+    mux_copy(&mut rkin, mux_with_tags(dst, h, opts, &tags)?)?;
+    Ok(h)
+}
+
+/// Copy an RKPI2 stream with its tags replaced by `new_tags`,
+/// or kept with `None`, leaving everything else as it is: the
+/// payload is copied byte for byte, never decoded, so editing
+/// the tags of a compressed stream doesn't cost recompressing it.
+/// Streams in footer mode have no header up-front to edit.
+#[cfg(any(test, not(feature = "no_std")))]
+pub fn remux<R: Read, W: Write>(src: R, dst: W,
+                                new_tags: Option<Vec<(String, String)>>)
+    -> Result<Hdr, RErr> {
+    let (mut src, mut dst) = (src, dst);
+    let (h, coding, tags) = read_header(&mut src, false, header::START_CODE)?;
+
+    let tags = match new_tags {
+        Some(t) => t,
+        None => header::decode_tags(&tags)?
+    };
+    let tags: Vec<(&str, &str)> = tags.iter()
+        .map(|(k, v)| (k.as_str(), v.as_str())).collect();
+
+    let hdr = header::encode_tagged(&h, coding, header::START_CODE, &tags)?;
+    if dst.write_all(&hdr).is_err() { return Err(RErr::IO) }
+    if std::io::copy(&mut src, &mut dst).is_err() { return Err(RErr::IO) }
+
+    Ok(h)
+}
+
+/// Read up to `n` bytes, stopping short only at the EOF.
+#[cfg(any(test, not(feature = "no_std")))]
+fn peek<R: Read>(r: &mut R, n: usize) -> Result<Vec<u8>, RErr> {
+    // `n` may come from the stream, so it isn't preallocated.
+    let mut buf = Vec::new();
+    match r.take(n as u64).read_to_end(&mut buf) {
+        Ok(_) => Ok(buf),
+        Err(_) => Err(RErr::IO)
+    }
+}
+
+#[cfg(feature = "compression")]
 #[test]
 fn rkpi2_hdr_and_data() {
   // use a pipe for redirecting the output got by RKPI2
@@ -113,7 +975,8 @@ fn rkpi2_hdr_and_data() {
   // cpu and ram load.
   let ihdr = Hdr { format: Fmt::Int8,
                    rate: 8000,
-                   channels: 1 };
+                   channels: 1,
+                   frames: None };
   
   let isamples = vec![127u8; ihdr.rate as usize 
                              * ihdr.channels as usize];
@@ -124,16 +987,1352 @@ fn rkpi2_hdr_and_data() {
 
   // use zstd with least power available to it, and also test
   // the compression method works correctly and produces
-  // accurate data provided to it, though it's not guranteed
-  // that this would always work.
- 
+  // accurate data provided to it.
   let mut rkout = mux(Box::new(out), ihdr, Some(1)).unwrap();
-  rkout.write(&isamples).unwrap();
-  rkout.flush().unwrap();
+  rkout.write_all(&isamples).unwrap();
+  rkout.finish().unwrap();
 
-  let (mut rkin, ohdr) = demux(Box::new(inp)).unwrap();
-  rkin.read(&mut osamples).unwrap();
+  let mut rkin = demux(Box::new(inp)).unwrap();
+  rkin.read_exact(&mut osamples).unwrap();
 
-  assert!(ihdr == ohdr);
+  assert!(ihdr == *rkin.header());
   assert!(isamples == osamples);
 }
+
+#[test]
+fn rkpi2_header_len() {
+  let mut h = Hdr { format: Fmt::Int16,
+                    rate: 22050,
+                    channels: 2,
+                    frames: None };
+
+  let rkout = mux(Vec::new(), h, None).unwrap();
+  assert!(rkout.header_len() == 2);
+  assert!(rkout.finish().unwrap().len() == 2);
+
+  h.frames = Some(1234);
+  let rkout = mux(Vec::new(), h, None).unwrap();
+  assert!(rkout.header_len() == 12);
+
+  let rkin = demux(Cursor::new(rkout.finish().unwrap())).unwrap();
+  assert!(*rkin.header() == h);
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn rkpi2_strict_payload() {
+  let h = Hdr { format: Fmt::Int8,
+                rate: 8000,
+                channels: 1,
+                frames: None };
+
+  // header claiming compression over raw bytes.
+  let mut file = mux(Vec::new(), h, None).unwrap().finish().unwrap();
+  file[0] |= 2;
+  file.extend_from_slice(&[1, 2, 3, 4, 5]);
+
+  let strict = DemuxOptions { strictness: Strictness::Strict,
+                             ..Default::default() };
+  assert!(demux(Cursor::new(file.clone())).is_ok());
+  assert!(matches!(demux_with_options(Cursor::new(file), strict),
+                   Err(RErr::Decompress)));
+
+  let mut rkout = mux(Vec::new(), h, Some(1)).unwrap();
+  rkout.write_all(&[1, 2, 3, 4, 5]).unwrap();
+  let mut rkin = demux_with_options(
+    Cursor::new(rkout.finish().unwrap()), strict).unwrap();
+
+  let mut out = Vec::new();
+  rkin.read_to_end(&mut out).unwrap();
+  assert!(out == [1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn rkpi2_misaligned_payload() {
+  let h = Hdr { format: Fmt::Int16,
+                rate: 8000,
+                channels: 2,
+                frames: None };
+  let strict = DemuxOptions { strictness: Strictness::Strict,
+                              ..Default::default() };
+  let file = encode_to_vec(h, &[1, 2, 3, 4, 5, 6, 7], None).unwrap();
+
+  let mut out = Vec::new();
+  demux(&file[..]).unwrap().read_to_end(&mut out).unwrap();
+  assert!(out.len() == 7);
+
+  let mut rkin = demux_with_options(&file[..], strict).unwrap();
+  let e = rkin.read_to_end(&mut Vec::new()).unwrap_err();
+  assert!(matches!(RErr::from(e), RErr::Misaligned));
+
+  let mut rkin = demux_with_options(&file[..], strict).unwrap();
+  let mut s = [0i16; 8];
+  assert!(matches!(rkin.read_i16_samples(&mut s), Err(RErr::Misaligned)));
+
+  let file = encode_to_vec(h, &[1, 2, 3, 4, 5, 6, 7, 8], None).unwrap();
+  let mut rkin = demux_with_options(&file[..], strict).unwrap();
+  assert!(rkin.read_i16_samples(&mut s).unwrap() == 4);
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn rkpi2_transcode() {
+  let h = Hdr { format: Fmt::Int16,
+                rate: 8000,
+                channels: 1,
+                frames: Some(4000) };
+  let pcm: Vec<u8> = (0..8000u32).map(|i| (i / 7) as u8).collect();
+  let tags = [("title", "ramp"), ("artist", "")];
+
+  let opts = MuxOptions { level: Some(1), ..Default::default() };
+  let mut rkout = mux_with_tags(Vec::new(), h, opts, &tags).unwrap();
+  rkout.write_all(&pcm).unwrap();
+  let lev1 = rkout.finish().unwrap();
+
+  let mut lev19 = Vec::new();
+  assert!(transcode(Cursor::new(&lev1), &mut lev19, Some(19)).unwrap() == h);
+  let mut back = Vec::new();
+  transcode(Cursor::new(&lev19), &mut back, Some(1)).unwrap();
+  assert!(back == lev1);
+
+  for file in [&lev19, &back].iter() {
+    let got = read_tags(Cursor::new(file)).unwrap();
+    assert!(got.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+               .eq(tags.iter().copied()));
+  }
+
+  let mut raw = Vec::new();
+  transcode(Cursor::new(lev19), &mut raw, None).unwrap();
+  assert!(read_tags(Cursor::new(&raw)).unwrap().len() == 2);
+
+  let mut rkin = demux(Cursor::new(raw)).unwrap();
+  let mut out = Vec::new();
+  rkin.read_to_end(&mut out).unwrap();
+
+  assert!(!rkin.compressed() && *rkin.header() == h);
+  assert!(out == pcm);
+}
+
+#[cfg(not(feature = "compression"))]
+#[test]
+fn rkpi2_compression_unsupported() {
+  let h = Hdr::default();
+  assert!(matches!(mux(Vec::new(), h, Some(1)),
+                   Err(RErr::CompressionUnsupported)));
+
+  let mut file = mux(Vec::new(), h, None).unwrap().finish().unwrap();
+  file[0] |= 2;
+  assert!(matches!(demux(Cursor::new(file)),
+                   Err(RErr::CompressionUnsupported)));
+}
+
+#[test]
+fn rkpi2_pcm_only() {
+  let h = Hdr { format: Fmt::Int16,
+                rate: 8000,
+                channels: 2,
+                frames: None };
+
+  let mut rkout = mux_pcm_only(Vec::new(), h).unwrap();
+  rkout.write_i16_samples(&[1, -2, 300, -400]).unwrap();
+  let pcm = rkout.finish().unwrap();
+  assert!(pcm.len() == 8);
+
+  let mut raw = Vec::new();
+  write_pcm_only(&mut raw, &pcm).unwrap();
+  assert!(raw == pcm);
+
+  let mut rkin = read_pcm_only(Cursor::new(raw), h).unwrap();
+  let mut s = [0i16; 4];
+  assert!(rkin.read_i16_samples(&mut s).unwrap() == 4);
+  assert!(s == [1, -2, 300, -400]);
+
+  #[cfg(feature = "compression")]
+  {
+    let z = zstd::encode_all(&pcm[..], 3).unwrap();
+    let mut rkin = demux_with_header(&z[..], h, Codec::Zstd).unwrap();
+    let mut s = [0i16; 4];
+    assert!(rkin.read_i16_samples(&mut s).unwrap() == 4);
+    assert!(s == [1, -2, 300, -400]);
+  }
+}
+
+#[test]
+fn rkpi2_max_srate() {
+  let h = Hdr { format: Fmt::Int8,
+                rate: 192000,
+                channels: 1,
+                frames: None };
+  let file = mux(Vec::new(), h, None).unwrap().finish().unwrap();
+  assert!(demux(Cursor::new(file)).unwrap().header().rate == 192000);
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn rkpi2_zstd_checksum() {
+  let h = Hdr { format: Fmt::Int8,
+                rate: 8000,
+                channels: 1,
+                frames: None };
+  let pcm: Vec<i8> = (0..4096u32)
+    .map(|i| (i.wrapping_mul(2654435761) >> 24) as i8).collect();
+
+  let opts = MuxOptions { level: Some(1), zstd_checksum: true,
+                          ..Default::default() };
+  let mut rkout = mux_with_options(Vec::new(), h, opts).unwrap();
+  rkout.write_i8_samples(&pcm).unwrap();
+  let mut file = rkout.finish().unwrap();
+
+  let mut s = vec![0i8; pcm.len()];
+  let mut rkin = demux(Cursor::new(file.clone())).unwrap();
+  assert!(rkin.read_i8_samples(&mut s).unwrap() == pcm.len());
+
+  let mid = file.len() / 2;
+  file[mid] ^= 0x01;
+  let mut rkin = demux(Cursor::new(file.clone())).unwrap();
+  assert!(matches!(rkin.read_i8_samples(&mut s), Err(RErr::Decompress)));
+
+  // whichever way the payload is read.
+  assert!(matches!(decode_from_slice(&file), Err(RErr::Decompress)));
+  assert!(matches!(transcode(&file[..], Vec::new(), None),
+                   Err(RErr::Decompress)));
+}
+
+#[test]
+fn rkpi2_mux_from_iter() {
+  let h = Hdr { format: Fmt::Float32,
+                rate: 8000,
+                channels: 1,
+                frames: None };
+  let tone = (0..10000).map(|i| (i as f32 * 440.0 / 8000.0
+                                 * std::f32::consts::TAU).sin());
+
+  let mut file = Vec::new();
+  assert!(mux_from_iter(&mut file, h, None, tone.clone()).unwrap() == 10000);
+
+  let mut rkin = demux(Cursor::new(file)).unwrap();
+  let mut s = vec![0f32; 10001];
+  assert!(rkin.read_f32_samples(&mut s).unwrap() == 10000);
+  assert!(s[..10000].iter().copied().eq(tone));
+}
+
+#[test]
+fn rkpi2_decode_from_slice() {
+  let h = Hdr { format: Fmt::Int16,
+                rate: 8000,
+                channels: 2,
+                frames: Some(1000) };
+  let pcm = vec![7u8; 4000];
+
+  let file = encode_to_vec(h, &pcm, None).unwrap();
+  let rkin = demux(Cursor::new(&file)).unwrap();
+  assert!(rkin.decoded_size_hint() == Some(4000));
+
+  let (ohdr, opcm) = decode_from_slice(&file).unwrap();
+  assert!(ohdr == h && opcm == pcm && opcm.capacity() == 4000);
+}
+
+#[test]
+fn rkpi2_mux_planar() {
+  let h = Hdr { format: Fmt::Int16,
+                rate: 8000,
+                channels: 2,
+                frames: None };
+  let (l, r) = ([0.5f32, -0.5, 0.25], [-1.0f32, 0.0, 0.75]);
+
+  let mut file = Vec::new();
+  mux_planar(&mut file, h, &[&l, &r], None).unwrap();
+  assert!(matches!(mux_planar(Vec::new(), h, &[&l], None),
+                   Err(RErr::Channels)));
+
+  let mut rkin = demux(Cursor::new(file)).unwrap();
+  let mut s = [0i16; 6];
+  assert!(rkin.read_i16_samples(&mut s).unwrap() == 6);
+  assert!(s == [16384, -32768, -16384, 0, 8192, 24576]);
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn rkpi2_large_window() {
+  use zstd::stream::{raw::Encoder, zio::Writer};
+  use zstd_safe::CParameter;
+
+  let h = Hdr { format: Fmt::Int8,
+                rate: 8000,
+                channels: 1,
+                frames: None };
+  let pcm: Vec<u8> = (0..5000u32).map(|i| (i % 7) as u8).collect();
+
+  // a window past the 2^27 libzstd accepts by default.
+  let mut enc = Encoder::new(3).unwrap();
+  enc.set_parameter(CParameter::WindowLog(28)).unwrap();
+  let hdr = header::encode(&h, Codec::Zstd.into(), header::START_CODE).unwrap();
+  let mut zw = Writer::new(hdr, enc);
+  zw.write_all(&pcm).unwrap();
+  zw.finish().unwrap();
+  let file = zw.into_inner().0;
+
+  let mut out = vec![0i8; pcm.len()];
+  let mut rkin = demux(Cursor::new(&file)).unwrap();
+  assert!(rkin.read_i8_samples(&mut out).unwrap() == pcm.len());
+  assert!(out.iter().zip(&pcm).all(|(&a, &b)| a as u8 == b));
+
+  let small = DemuxOptions { window_log_max: Some(27),
+                             ..Default::default() };
+  let mut rkin = demux_with_options(Cursor::new(&file), small).unwrap();
+  assert!(matches!(rkin.read_i8_samples(&mut out), Err(RErr::Decompress)));
+}
+
+#[test]
+fn rkpi2_frame_count() {
+  let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 2, frames: None };
+  let pcm = vec![3u8; 4000];
+
+  let file = encode_to_vec(h, &pcm, None).unwrap();
+  assert!(frame_count(&file[..]).unwrap().is_none());
+  let file = encode_to_vec(Hdr { frames: Some(1000), ..h }, &pcm, None).unwrap();
+  assert!(frame_count(&file[..]).unwrap() == Some(1000));
+
+  #[cfg(feature = "compression")]
+  {
+    let file = encode_to_vec(h, &pcm, Some(3)).unwrap();
+    assert!(frame_count(&file[..]).unwrap().is_none());
+
+    // compressed in one go, so the frame header has the size.
+    let mut file = header::encode(&h, Codec::Zstd.into(), header::START_CODE)
+      .unwrap();
+    let mut z = vec![0u8; zstd_safe::compress_bound(pcm.len())];
+    let n = zstd_safe::compress(&mut z, &pcm, 3).unwrap();
+    file.extend_from_slice(&z[..n]);
+    assert!(frame_count(&file[..]).unwrap() == Some(1000));
+  }
+}
+
+#[test]
+fn rkpi2_write_header() {
+  let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 1, frames: Some(4) };
+  let pcm = [1u8, 0, 2, 0, 3, 0, 4, 0];
+
+  let mut file = Vec::new();
+  assert!(write_header(&mut file, h, false).unwrap() == 12);
+  for c in pcm.chunks(2) { write_pcm_only(&mut file, c).unwrap() }
+  assert!(decode_from_slice(&file).unwrap() == (h, pcm.to_vec()));
+
+  #[cfg(feature = "compression")]
+  {
+    let mut file = Vec::new();
+    write_header(&mut file, h, true).unwrap();
+    file.extend(zstd::encode_all(&pcm[..], 3).unwrap());
+    assert!(decode_from_slice(&file).unwrap() == (h, pcm.to_vec()));
+  }
+
+  let bad = Hdr { rate: 48001, ..h };
+  assert!(matches!(write_header(&mut Vec::new(), bad, false), Err(RErr::Rate)));
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn rkpi2_estimate_ratio() {
+  // quiet noise, compressing about as well everywhere.
+  let mut x = 1u32;
+  let pcm: Vec<u8> = (0..4 << 20).map(|i| match i % 2 {
+    0 => { x ^= x << 13; x ^= x >> 17; x ^= x << 5; x as u8 & 0x1f },
+    _ => 0
+  }).collect();
+
+  let full = pcm.len() as f64 / zstd::encode_all(&pcm[..], 3).unwrap().len() as f64;
+  let est = estimate_ratio(&pcm, 3);
+  assert!((est - full).abs() / full < 0.1, "{} vs {}", est, full);
+
+  assert!(estimate_ratio(&[], 3) == 1.0);
+  assert!(estimate_ratio(&[0; 1000], 3) > 10.0);
+}
+
+#[test]
+fn rkpi2_demux_chunk() {
+  let h = Hdr { format: Fmt::Int8, rate: 8000, channels: 1, frames: None };
+  let next = b"NEXT";
+
+  for &lev in &[None, Some(3)] {
+    if lev.is_some() && cfg!(not(feature = "compression")) { continue }
+
+    let mut stream = encode_to_vec(h, &[7; 500], lev).unwrap();
+    let len = stream.len() as u64;
+    stream.extend_from_slice(next);
+
+    let (mut rkin, used) = demux_chunk(&stream[..], len).unwrap();
+    assert!(used == 2);
+
+    let mut out = Vec::new();
+    rkin.read_to_end(&mut out).unwrap();
+    assert!(out == [7; 500]);
+    assert!(rkin.into_inner().into_inner() == next);
+  }
+}
+
+#[test]
+fn rkpi2_absurd_frame_count() {
+  let h = Hdr { format: Fmt::Int64,
+                rate: 8000,
+                channels: 8,
+                frames: Some(u64::MAX) };
+  let file = encode_to_vec(h, &[0; 64], None).unwrap();
+  assert!(matches!(decode_from_slice(&file), Err(RErr::Truncated)));
+
+  #[cfg(feature = "compression")]
+  {
+    let file = encode_to_vec(h, &[0; 64], Some(1)).unwrap();
+    let opts = DemuxOptions { max_prealloc: Some(1024),
+                              ..Default::default() };
+    assert!(matches!(decode_from_slice_with_options(&file, opts),
+                     Err(RErr::Truncated)));
+  }
+
+  let h = Hdr { frames: Some(1), ..h };
+  let file = encode_to_vec(h, &[0; 64], None).unwrap();
+  assert!(decode_from_slice(&file).unwrap().1.len() == 64);
+}
+
+#[test]
+fn rkpi2_max_encoded_size() {
+  let h = Hdr { format: Fmt::Int16,
+                rate: 22050,
+                channels: 2,
+                frames: Some(3000) };
+  let pcm: Vec<u8> = (0..12000u32).map(|i| ((i * 7919) >> 3) as u8).collect();
+
+  let raw = encode_to_vec(h, &pcm, None).unwrap();
+  assert!(max_encoded_size(&h, 3000, None) == raw.len() as u64);
+
+  // only `mux` with its defaults is covered, not the trailer.
+  let opts = MuxOptions { pcm_crc: true, ..Default::default() };
+  let mut rkout = mux_with_options(Vec::new(), h, opts).unwrap();
+  rkout.write_all(&pcm).unwrap();
+  let crc = rkout.finish().unwrap();
+  assert!(crc.len() as u64 > max_encoded_size(&h, 3000, None));
+
+  #[cfg(feature = "compression")]
+  for &n in &[0, 1, 3000] {
+    let len = n * 4;
+    let file = encode_to_vec(h, &pcm[..len], Some(19)).unwrap();
+    let bound = max_encoded_size(&h, n as u64, Some(19));
+    assert!(file.len() as u64 <= bound);
+    assert!(bound == 12 + zstd_safe::compress_bound(len) as u64);
+  }
+}
+
+#[test]
+fn rkpi2_backfill_frames() {
+  use std::io::{self, SeekFrom};
+
+  // a pipe, as far as seeking is concerned.
+  struct Pipe(Vec<u8>);
+  impl Write for Pipe {
+    fn write(&mut self, b: &[u8]) -> io::Result<usize> { self.0.write(b) }
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+  }
+  impl Seek for Pipe {
+    fn seek(&mut self, _: SeekFrom) -> io::Result<u64> {
+      Err(io::Error::other("illegal seek"))
+    }
+  }
+
+  let h = Hdr { format: Fmt::Int16,
+                rate: 8000,
+                channels: 2,
+                frames: None };
+  let opts = MuxOptions { backfill_frames: true, ..Default::default() };
+
+  assert!(matches!(mux_with_options(Vec::new(), h, opts),
+                   Err(RErr::Unseekable)));
+  assert!(matches!(mux_seekable(Pipe(Vec::new()), h, opts),
+                   Err(RErr::Unseekable)));
+
+  let mut levels = vec![None];
+  if cfg!(feature = "compression") { levels.push(Some(3)) }
+
+  for level in levels {
+    let mut out = Cursor::new(vec![0xaa; 3]);
+    out.set_position(3);
+
+    let opts = MuxOptions { level, ..opts };
+    let mut rkout = mux_seekable(out, h, opts).unwrap();
+    rkout.write_i16_samples(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]).unwrap();
+    let out = rkout.finish().unwrap();
+    assert!(out.position() == out.get_ref().len() as u64);
+
+    let (oh, pcm) = decode_from_slice(&out.get_ref()[3..]).unwrap();
+    assert!(oh.frames == Some(5) && pcm.len() == 20);
+  }
+}
+
+#[test]
+fn rkpi2_codec_roundtrip() {
+  let h = Hdr { format: Fmt::Int8,
+                rate: 8000,
+                channels: 1,
+                frames: None };
+  let mut levels = vec![(None, Codec::None)];
+  if cfg!(feature = "compression") { levels.push((Some(3), Codec::Zstd)) }
+
+  for (lev, codec) in levels {
+    let rkout = mux(Vec::new(), h, lev).unwrap();
+    assert!(rkout.codec() == codec);
+    rkout.abandon();
+    let file = encode_to_vec(h, &[1, 2, 3], lev).unwrap();
+
+    let mut rkin = demux(Cursor::new(&file)).unwrap();
+    let mut out = Vec::new();
+    rkin.read_to_end(&mut out).unwrap();
+    assert!(rkin.codec() == codec && out == [1, 2, 3]);
+  }
+
+  // an extended header naming a codec from the reserved range.
+  let file = [0xf7, 0xc0, 0x00, 0x02, 0x05];
+  assert!(matches!(demux(&file[..]), Err(RErr::Codec)));
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn rkpi2_deterministic() {
+  let h = Hdr { format: Fmt::Int16,
+                rate: 44100,
+                channels: 2,
+                frames: Some(20000) };
+  let pcm: Vec<u8> = (0..80000u64).map(|i| ((i * i) >> 9) as u8).collect();
+
+  let run = |opts: MuxOptions| {
+    let mut rkout = mux_with_options(Vec::new(), h, opts).unwrap();
+    rkout.write_all(&pcm[..30000]).unwrap();
+    rkout.flush_frame().unwrap();
+    rkout.write_all(&pcm[30000..]).unwrap();
+    rkout.finish().unwrap()
+  };
+
+  for &level in &[1, 3, 19] {
+    for &zstd_checksum in &[false, true] {
+      let opts = MuxOptions { level: Some(level), zstd_checksum,
+                              ..Default::default() };
+      assert!(run(opts) == run(opts));
+    }
+  }
+}
+
+#[test]
+fn rkpi2_custom_start_code() {
+  let h = Hdr { format: Fmt::Int8,
+                rate: 8000,
+                channels: 1,
+                frames: Some(3) };
+  let mopts = MuxOptions { start_code: Some(0x2a), ..Default::default() };
+  let dopts = DemuxOptions { start_code: Some(0x2a), ..Default::default() };
+
+  let mut rkout = mux_with_options(Vec::new(), h, mopts).unwrap();
+  rkout.write_all(&[1, 2, 3]).unwrap();
+  let file = rkout.finish().unwrap();
+  assert!(file[0] >> 2 == 0x2a);
+
+  assert!(decode_from_slice_with_options(&file, dopts).unwrap()
+          == (h, vec![1, 2, 3]));
+  assert!(matches!(demux(&file[..]), Err(RErr::StartCode)));
+  assert!(matches!(decode_from_slice(&file), Err(RErr::StartCode)));
+
+  let bad = MuxOptions { start_code: Some(0x40), ..Default::default() };
+  assert!(matches!(mux_with_options(Vec::new(), h, bad),
+                   Err(RErr::StartCode)));
+}
+
+#[test]
+fn rkpi2_footer_mode() {
+  let h = Hdr { format: Fmt::Int16,
+                rate: 8000,
+                channels: 2,
+                frames: None };
+  let mut levels = vec![None];
+  if cfg!(feature = "compression") { levels.push(Some(3)) }
+
+  for level in levels {
+    let opts = MuxOptions { level, footer: true, ..Default::default() };
+    let mut rkout = mux_with_options(Vec::new(), h, opts).unwrap();
+    rkout.write_i16_samples(&[1, 2, 3, 4, 5, 6]).unwrap();
+    let file = rkout.finish().unwrap();
+
+    let mut rkin = demux_footer(Cursor::new(file)).unwrap();
+    assert!(*rkin.header() == Hdr { frames: Some(3), ..h });
+
+    let mut s = [0i16; 8];
+    assert!(rkin.read_i16_samples(&mut s).unwrap() == 6);
+    assert!(s[..6] == [1, 2, 3, 4, 5, 6]);
+  }
+
+  assert!(matches!(demux_footer(Cursor::new([0xf4])), Err(RErr::Truncated)));
+}
+
+#[test]
+fn rkpi2_silence_rle() {
+  let h = Hdr { format: Fmt::Int16,
+                rate: 8000,
+                channels: 2,
+                frames: None };
+  let mut pcm = vec![0i16; 200_000];
+  for (i, s) in pcm.iter_mut().enumerate().skip(50_000).take(1000) {
+    *s = (i % 97) as i16 * 300;
+  }
+  pcm[199_999] = 7;
+
+  let mut levels = vec![None];
+  if cfg!(feature = "compression") { levels.push(Some(1)) }
+
+  for level in levels {
+    let mux = |silence_rle| {
+      let opts = MuxOptions { level, silence_rle, ..Default::default() };
+      let mut rkout = mux_with_options(Vec::new(), h, opts).unwrap();
+      for c in pcm.chunks(3001) { rkout.write_i16_samples(c).unwrap() }
+      rkout.finish().unwrap()
+    };
+    let (plain, rle) = (mux(false), mux(true));
+    assert!(rle.len() < plain.len());
+
+    let mut rkin = demux(&rle[..]).unwrap();
+    let mut out = vec![0i16; pcm.len() + 1];
+    let mut n = 0;
+    loop {
+      match rkin.read_i16_samples(&mut out[n..]).unwrap() {
+        0 => break,
+        k => n += k
+      }
+    }
+    assert!(n == pcm.len() && out[..n] == pcm[..]);
+  }
+}
+
+#[test]
+fn rkpi2_scan_dir() {
+  let dir = std::env::temp_dir()
+    .join(format!("rkpi2-scan-{}", std::process::id()));
+  fs::create_dir_all(&dir).unwrap();
+
+  let h = Hdr { format: Fmt::Int16,
+                rate: 22050,
+                channels: 1,
+                frames: Some(2) };
+  fs::write(dir.join("a.rkpi2"), encode_to_vec(h, &[0; 4], None).unwrap())
+    .unwrap();
+  fs::write(dir.join("b.rkpi2"), [0xff, 0xff, 0, 0]).unwrap();
+  fs::write(dir.join("c.txt"), "not audio").unwrap();
+
+  let res = scan_dir(&dir);
+  fs::remove_dir_all(&dir).unwrap();
+
+  assert!(res.len() == 2);
+  assert!(res[0].0.ends_with("a.rkpi2") && res[0].1.as_ref().ok() == Some(&h));
+  assert!(res[1].0.ends_with("b.rkpi2")
+          && matches!(res[1].1, Err(RErr::StartCode)));
+  assert!(matches!(scan_dir(dir.join("gone"))[..], [(_, Err(RErr::IO))]));
+}
+
+#[test]
+fn rkpi2_transcode_dir() {
+  use wav::wav_file;
+
+  let dir = std::env::temp_dir()
+    .join(format!("rkpi2-transcode-{}", std::process::id()));
+  let (src, dst) = (dir.join("in"), dir.join("out"));
+  fs::create_dir_all(&src).unwrap();
+  fs::create_dir_all(&dst).unwrap();
+
+  let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 1,
+                frames: Some(4000) };
+  let pcm: Vec<u8> = (0..8000u32).map(|i| (i % 7) as u8).collect();
+  for n in ["a.wav", "b.WAV"].iter() {
+    fs::write(src.join(n), wav_file(h.format, h.rate, 1, &pcm)).unwrap();
+  }
+  fs::write(src.join("notes.txt"), b"not audio").unwrap();
+
+  let opts = MuxOptions { pcm_crc: true, ..Default::default() };
+  let res = transcode_dir(&src, &dst, opts);
+  let outs: Vec<_> = ["a.rkpi2", "b.rkpi2"].iter()
+    .map(|n| fs::read(dst.join(n)).unwrap()).collect();
+  fs::remove_dir_all(&dir).unwrap();
+
+  assert!(res.len() == 2 && res.iter().all(|r| r.1.is_ok()));
+  assert!(res[0].0.ends_with("a.wav") && res[1].0.ends_with("b.WAV"));
+  for o in outs {
+    assert!(decode_from_slice(&o).unwrap() == (h, pcm.clone()));
+  }
+
+  // RKPI2 inputs keep their tags, and over itself they're refused
+  // and left untouched, as is a WAV file going to the same name.
+  fs::create_dir_all(&src).unwrap();
+  fs::create_dir_all(&dst).unwrap();
+  let tags = [("title", "tone")];
+  let mut rkout = mux_with_tags(Vec::new(), h, MuxOptions::default(),
+                                &tags).unwrap();
+  rkout.write_all(&pcm).unwrap();
+  let file = rkout.finish().unwrap();
+  fs::write(src.join("a.rkpi2"), &file).unwrap();
+  fs::write(src.join("a.wav"), wav_file(h.format, h.rate, 1, &pcm)).unwrap();
+  let copied = transcode_dir(&src, &dst, opts);
+  let tagged = read_tags(&fs::read(dst.join("a.rkpi2")).unwrap()[..]).unwrap();
+  let res = transcode_dir(&src, &src, opts);
+  let left = fs::read(src.join("a.rkpi2")).unwrap();
+  let entries = fs::read_dir(&src).unwrap().count();
+
+  // the reader's error comes through, and nothing is left behind.
+  fs::remove_dir_all(&dst).unwrap();
+  fs::create_dir_all(&dst).unwrap();
+  fs::remove_file(src.join("a.wav")).unwrap();
+  let mut rkout = mux_with_options(Vec::new(), h, opts).unwrap();
+  rkout.write_all(&pcm).unwrap();
+  let mut bad = rkout.finish().unwrap();
+  bad[100] ^= 1;
+  fs::write(src.join("a.rkpi2"), &bad).unwrap();
+  let failed = transcode_dir(&src, &dst, opts);
+  let outs = fs::read_dir(&dst).unwrap().count();
+  fs::remove_dir_all(&dir).unwrap();
+
+  assert!(matches!(copied[..], [(_, Ok(())), (_, Err(RErr::IO))]));
+  assert!(tagged == [("title".to_string(), "tone".to_string())]);
+  assert!(matches!(res[..], [(_, Err(RErr::IO)), (_, Err(RErr::IO))]));
+  assert!(left == file && entries == 2);
+  assert!(matches!(failed[..], [(_, Err(RErr::Checksum))]));
+  assert!(outs == 0);
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn rkpi2_read_until_eof() {
+  // hands out a few bytes at a time, like a pipe would.
+  struct Trickle<'a>(&'a [u8]);
+  impl Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+      let n = buf.len().min(self.0.len()).min(3);
+      buf[..n].copy_from_slice(&self.0[..n]);
+      self.0 = &self.0[n..];
+      Ok(n)
+    }
+  }
+
+  let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 2, frames: None };
+  let pcm: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+
+  for &lev in [None, Some(3)].iter() {
+    let rk = encode_to_vec(h, &pcm, lev).unwrap();
+    let mut rkin = demux(Trickle(&rk)).unwrap();
+    let mut out = Vec::new();
+    rkin.read_to_end(&mut out).unwrap();
+
+    assert!(out == pcm);
+    assert!(rkin.read(&mut [0; 16]).unwrap() == 0);
+  }
+
+  // a Zstd frame that never ends is an error, not an early EOF.
+  let rk = encode_to_vec(h, &pcm, Some(3)).unwrap();
+  let mut rkin = demux(Trickle(&rk[..rk.len() - 4])).unwrap();
+  assert!(rkin.read_to_end(&mut Vec::new()).is_err());
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn rkpi2_decompressed_limit() {
+  let h = Hdr { format: Fmt::Int8, rate: 8000, channels: 1, frames: None };
+  let rk = encode_to_vec(h, &vec![0; 1 << 20], Some(19)).unwrap();
+  assert!(rk.len() < 1024);
+
+  let opts = |m| DemuxOptions { max_decompressed_bytes: Some(m),
+                                ..Default::default() };
+  assert!(matches!(decode_from_slice_with_options(&rk, opts(1 << 16)),
+                   Err(RErr::LimitExceeded)));
+  assert!(decode_from_slice_with_options(&rk, opts(1 << 20)).unwrap().1
+          .len() == 1 << 20);
+
+  use std::io::BufRead;
+  let mut rkin = demux_with_options(&rk[..], opts(10)).unwrap();
+  assert!(rkin.fill_buf().unwrap().len() == 10);
+  rkin.consume(10);
+  assert!(rkin.fill_buf().is_err());
+}
+
+#[test]
+fn rkpi2_probe() {
+  let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 2, frames: None };
+  let pcm = [1u8; 64];
+  let framed = encode_to_vec(Hdr { frames: Some(16), ..h }, &pcm, None).unwrap();
+  let raw = encode_to_vec(h, &pcm, None).unwrap();
+
+  assert!(probe(&framed) == Probe::Definite);
+  assert!(probe(&raw) == Probe::Maybe && probe(&raw[..1]) == Probe::Maybe);
+  assert!(probe(&framed[..5]) == Probe::Maybe);
+  assert!(probe(b"RIFF\x24\0\0\0WAVEfmt ") == Probe::No && probe(&[]) == Probe::No);
+
+  #[cfg(feature = "compression")] {
+    let z = encode_to_vec(h, &pcm, Some(3)).unwrap();
+    assert!(probe(&z) == Probe::Definite && probe(&z[..4]) == Probe::Maybe);
+  }
+
+  let mut x = 0x9e3779b9u32;
+  for _ in 0..1000 {
+    let b: Vec<u8> = (0..16).map(|_| {
+      x = x.wrapping_mul(1664525).wrapping_add(1013904223);
+      (x >> 24) as u8
+    }).collect();
+    assert!(probe(&b) != Probe::Definite);
+  }
+}
+
+#[test]
+fn rkpi2_top_channel_counts() {
+  for &channels in [7u8, 8].iter() {
+    let h = Hdr { format: Fmt::Int8, rate: 8000, channels, frames: None };
+    let planar: Vec<Vec<u8>> = (0..channels)
+      .map(|c| (0..100u8).map(|i| c * 16 + i % 16).collect())
+      .collect();
+    let planes: Vec<&[u8]> = planar.iter().map(|c| &c[..]).collect();
+
+    let rk = encode_to_vec(h, &interleave(&planes).unwrap(), None).unwrap();
+    assert!(rk[1] & 7 == channels - 1);
+
+    let (dh, pcm) = decode_from_slice(&rk).unwrap();
+    assert!(dh == h && pcm.len() == 100 * channels as usize);
+    for (c, plane) in planar.iter().enumerate() {
+      assert!(extract_channel(&pcm, channels as usize, c).unwrap() == *plane);
+    }
+  }
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn rkpi2_empty_compressed() {
+  let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 2, frames: None };
+  let strict = DemuxOptions { strictness: Strictness::Strict,
+                              ..Default::default() };
+  let checked = MuxOptions { level: Some(3), silence_rle: true,
+                             pcm_crc: true, ..Default::default() };
+
+  let files = [
+    mux(Vec::new(), h, Some(3)).unwrap().finish().unwrap(),
+    mux(Vec::new(), Hdr { frames: Some(0), ..h }, Some(3)).unwrap()
+      .finish().unwrap(),
+    mux_with_options(Vec::new(), h, checked).unwrap().finish().unwrap()
+  ];
+  for f in files.iter() {
+    let mut rkin = demux_with_options(&f[..], strict).unwrap();
+    assert!(rkin.read(&mut [0; 64]).unwrap() == 0);
+    assert!(decode_from_slice(f).unwrap().1.is_empty());
+  }
+
+  // frames flushed with nothing in them decode to nothing too.
+  let mut rkout = mux(Vec::new(), h, Some(3)).unwrap();
+  rkout.flush_frame().unwrap();
+  rkout.write_all(&[1; 8]).unwrap();
+  rkout.flush_frame().unwrap();
+  rkout.flush_frame().unwrap();
+  let f = rkout.finish().unwrap();
+  assert!(decode_from_slice(&f).unwrap().1 == [1; 8]);
+}
+
+#[test]
+fn rkpi2_encode_into_vec() {
+  let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 1, frames: None };
+  let a: Vec<u8> = (0..4000u32).map(|i| (i % 13) as u8).collect();
+  let b = [5u8; 30];
+  let lev = if cfg!(feature = "compression") { Some(3) } else { None };
+
+  let mut out = Vec::new();
+  encode_into_vec(h, &a, lev, &mut out).unwrap();
+  assert!(out == encode_to_vec(h, &a, lev).unwrap());
+
+  let cap = out.capacity();
+  encode_into_vec(h, &b, lev, &mut out).unwrap();
+  assert!(out == encode_to_vec(h, &b, lev).unwrap() && out.capacity() == cap);
+}
+
+#[test]
+fn rkpi2_merge_mono() {
+  let h = Hdr { format: Fmt::Int16, rate: 22050, channels: 1, frames: None };
+  let left = encode_to_vec(h, &[1, 0, 2, 0, 3, 0], None).unwrap();
+  let right = encode_to_vec(h, &[9, 0, 8, 0], None).unwrap();
+
+  let mut out = Vec::new();
+  merge_mono_to_stereo(&left[..], &right[..], 22050, None, true,
+                       &mut out).unwrap();
+  let (oh, pcm) = decode_from_slice(&out).unwrap();
+  assert!(oh == Hdr { channels: 2, ..h });
+  assert!(pcm == [1, 0, 9, 0, 2, 0, 8, 0, 3, 0, 0, 0]);
+
+  assert!(matches!(merge_mono_to_stereo(&left[..], &right[..], 22050, None,
+                                        false, Vec::new()),
+                   Err(RErr::Truncated)));
+  let stereo = encode_to_vec(Hdr { channels: 2, ..h }, &[], None).unwrap();
+  assert!(matches!(merge_mono_to_stereo(&left[..], &stereo[..], 22050, None,
+                                        true, Vec::new()),
+                   Err(RErr::Channels)));
+  let fast = encode_to_vec(Hdr { rate: 44100, ..h }, &[], None).unwrap();
+  assert!(matches!(merge_mono_to_stereo(&fast[..], &right[..], 22050, None,
+                                        true, Vec::new()),
+                   Err(RErr::Mismatch)));
+  assert!(matches!(merge_mono_to_stereo(&left[..], &right[..], 44100, None,
+                                        true, Vec::new()),
+                   Err(RErr::Mismatch)));
+  let odd = encode_to_vec(h, &[9, 0, 8], None).unwrap();
+  assert!(matches!(merge_mono_to_stereo(&left[..], &odd[..], 22050, None,
+                                        true, Vec::new()),
+                   Err(RErr::Misaligned)));
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn rkpi2_footer_index() {
+  let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 2, frames: None };
+  let pcm: Vec<u8> = (0..16000u32).map(|i| ((i * 31) >> 4) as u8).collect();
+  let opts = MuxOptions { level: Some(3), pcm_crc: true,
+                          index_interval: Some(1000), ..Default::default() };
+
+  let mut rkout = mux_with_options(Vec::new(), h, opts).unwrap();
+  for c in pcm.chunks(777) { rkout.write_all(c).unwrap() }
+  let index = rkout.seek_index().clone();
+  let file = rkout.finish().unwrap();
+  assert!(index.points().iter().map(|p| p.0).eq([4000, 8000, 12000, 16000]));
+
+  // readers unaware of the index step over it.
+  assert!(decode_from_slice(&file).unwrap().1 == pcm);
+
+  let mut rkin = demux_seekable(Cursor::new(file.clone()),
+                                DemuxOptions::default()).unwrap();
+  assert!(rkin.seek_index() == Some(&index));
+  rkin.seek_to_sample(2500).unwrap();
+  let mut s = [0u8; 8];
+  rkin.read_exact(&mut s).unwrap();
+  assert!(s == pcm[10000..10008]);
+  rkin.seek_to_sample(3).unwrap();
+  rkin.read_exact(&mut s).unwrap();
+  assert!(s == pcm[12..20]);
+
+  let plain = encode_to_vec(h, &pcm, None).unwrap();
+  let mut rkin = demux_seekable(Cursor::new(plain),
+                                DemuxOptions::default()).unwrap();
+  assert!(rkin.seek_index().unwrap().points().is_empty());
+  rkin.seek_to_sample(3999).unwrap();
+  rkin.read_exact(&mut s[..4]).unwrap();
+  assert!(s[..4] == pcm[15996..]);
+
+  let bare = encode_to_vec(h, &pcm, Some(3)).unwrap();
+  let rkin = demux_seekable(Cursor::new(bare), DemuxOptions::default());
+  assert!(rkin.unwrap().seek_index().is_none());
+}
+
+#[test]
+fn rkpi2_would_block() {
+  // past the header, every other read has nothing ready yet.
+  struct Flaky<'a>(&'a [u8], usize, bool);
+  impl Read for Flaky<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+      self.2 = !self.2;
+      if self.1 > 16 && self.2 {
+        return Err(std::io::ErrorKind::WouldBlock.into())
+      }
+
+      let n = buf.len().min(self.0.len()).min(3);
+      buf[..n].copy_from_slice(&self.0[..n]);
+      self.0 = &self.0[n..];
+      self.1 += n;
+      Ok(n)
+    }
+  }
+
+  let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 2, frames: None };
+  // silences and sounds in turn, so there are many RLE records.
+  let pcm: Vec<u8> = (0..8000u32)
+    .map(|i| if i % 300 < 200 { 0 } else { (i % 251) as u8 | 1 }).collect();
+
+  let mut all = vec![MuxOptions { silence_rle: true, pcm_crc: true,
+                                  ..Default::default() }];
+  if cfg!(feature = "compression") {
+    all.push(MuxOptions { level: Some(3), ..Default::default() });
+    all.push(MuxOptions { level: Some(3), silence_rle: true, pcm_crc: true,
+                          ..Default::default() });
+  }
+
+  for opts in all {
+    let mut rkout = mux_with_options(Vec::new(), h, opts).unwrap();
+    rkout.write_all(&pcm).unwrap();
+    let rk = rkout.finish().unwrap();
+
+    let mut rkin = demux(Flaky(&rk, 0, false)).unwrap();
+    let (mut out, mut blocked) = (Vec::new(), 0);
+    let mut buf = [0u8; 100];
+    loop {
+      match rkin.read(&mut buf) {
+        Ok(0) => break,
+        Ok(n) => out.extend_from_slice(&buf[..n]),
+        Err(e) => {
+          assert!(e.kind() == std::io::ErrorKind::WouldBlock);
+          assert!(matches!(RErr::from(e), RErr::WouldBlock));
+          blocked += 1;
+        }
+      }
+    }
+
+    assert!(out == pcm && blocked > 0);
+
+    // reads of whole samples and frames, blocked partway through
+    // one, pick up where they left off.
+    let mut rkin = demux(Flaky(&rk, 0, false)).unwrap();
+    let (mut s, mut samples) = ([0i16; 50], Vec::new());
+    loop {
+      match rkin.read_i16_samples(&mut s) {
+        Ok(0) => break,
+        Ok(n) => samples.extend_from_slice(&s[..n]),
+        Err(e) => assert!(matches!(e, RErr::WouldBlock))
+      }
+    }
+    let bytes: Vec<u8> = samples.iter().flat_map(|x| x.to_le_bytes()).collect();
+    assert!(bytes == pcm);
+
+    let mut rkin = demux(Flaky(&rk, 0, false)).unwrap();
+    let (mut frame, mut frames) = ([0u8; 4], Vec::new());
+    loop {
+      match rkin.read_frame_into(&mut frame) {
+        Ok(0) => break,
+        Ok(n) => frames.extend_from_slice(&frame[..n]),
+        Err(e) => assert!(matches!(e, RErr::WouldBlock))
+      }
+    }
+    assert!(frames == pcm);
+
+    let mut rkin = demux(Flaky(&rk, 0, false)).unwrap();
+    let slice = loop {
+      match rkin.slice(10, 1000) {
+        Ok(s) => break s,
+        Err(e) => assert!(matches!(e, RErr::WouldBlock))
+      }
+    };
+    assert!(slice == pcm[40..4000]);
+  }
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn rkpi2_zstd_version() {
+  let v = zstd_version();
+  let n = zstd_safe::version_number();
+  assert!(v == format!("{}.{}.{}", n / 10000, n / 100 % 100, n % 100));
+}
+
+#[test]
+fn rkpi2_unknown_format() {
+  // format code 6 in the standard header, then in the extended
+  // one with a frame count, both 44100Hz stereo.
+  let short = [0xf5, 0xa1, 1, 2, 3, 4];
+  let ext = [0xf5, 0xe1, 0x06, 0x01, 2, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4];
+  let pass = DemuxOptions { on_unknown_format: UnknownFormat::Passthrough,
+                           ..Default::default() };
+
+  for &b in &[&short[..], &ext[..]] {
+    assert!(matches!(demux(b), Err(RErr::Format)));
+
+    let mut rkin = demux_with_options(b, pass).unwrap();
+    assert!(rkin.unknown_format() == Some(6));
+    assert!(rkin.header().format == Fmt::Int8 && rkin.header().channels == 2);
+    assert!(rkin.header().rate == 44100);
+
+    // nothing going by samples or frames gets anywhere.
+    let mut f = [0f32; 2];
+    assert!(matches!(rkin.read_f32_samples(&mut f), Err(RErr::Format)));
+    assert!(matches!(rkin.read_i8_samples(&mut [0; 2]), Err(RErr::Format)));
+    assert!(matches!(rkin.skip_frames(1), Err(RErr::Format)));
+    assert!(rkin.frames_remaining().is_none() && rkin.decoded_size_hint().is_none());
+
+    let mut out = Vec::new();
+    rkin.read_to_end(&mut out).unwrap();
+    assert!(out == [1, 2, 3, 4] && rkin.position_frames() == 0);
+
+    let mut conv = demux_with_options(b, pass).unwrap().convert_to(Fmt::Int16);
+    assert!(matches!(RErr::from(conv.read(&mut [0; 4]).unwrap_err()), RErr::Format));
+  }
+
+  assert!(demux_with_options(&encode_to_vec(Hdr::default(), &[0; 4], None)
+                             .unwrap()[..], pass).unwrap()
+          .unknown_format().is_none());
+}
+
+#[test]
+fn rkpi2_extension() {
+  assert!(is_rkpi2_extension("a/b.rkpi2") && is_rkpi2_extension("B.RKPI2"));
+  assert!(!is_rkpi2_extension("b.rkpi2.wav") && !is_rkpi2_extension("rkpi2"));
+  assert!(MIME_TYPE.starts_with("audio/"));
+}
+
+#[test]
+fn rkpi2_pcm_crc() {
+  let h = Hdr { format: Fmt::Int16,
+                rate: 8000,
+                channels: 1,
+                frames: None };
+  let mut pcm = vec![0u8; 4000];
+  pcm.extend((0..1000u32).map(|i| (i * 7) as u8));
+
+  let mut opts = vec![MuxOptions { pcm_crc: true, ..Default::default() },
+                      MuxOptions { pcm_crc: true, silence_rle: true,
+                                   ..Default::default() }];
+  if cfg!(feature = "compression") {
+    opts.push(MuxOptions { level: Some(3), pcm_crc: true, silence_rle: true,
+                           ..Default::default() });
+  }
+
+  for o in opts {
+    let mut rkout = mux_with_options(Vec::new(), h, o).unwrap();
+    rkout.write_all(&pcm).unwrap();
+    let file = rkout.finish().unwrap();
+    assert!(decode_from_slice(&file).unwrap().1 == pcm);
+  }
+
+  let opts = MuxOptions { pcm_crc: true, ..Default::default() };
+  let mut rkout = mux_with_options(Vec::new(), h, opts).unwrap();
+  rkout.write_all(&pcm).unwrap();
+  let file = rkout.finish().unwrap();
+
+  let mut bad = file.clone();
+  bad[4 + 4500] ^= 1;
+  assert!(matches!(decode_from_slice(&bad), Err(RErr::Checksum)));
+
+  // too short to even hold the CRC.
+  assert!(matches!(decode_from_slice(&file[..6]), Err(RErr::Truncated)));
+}
+
+#[test]
+fn rkpi2_delta_filter() {
+  use utils::testutil::generate_tone;
+
+  let h = Hdr { format: Fmt::Int16, rate: 44100, channels: 2, frames: None };
+  let pcm = generate_tone(441.7, &h, 50_000);
+
+  let mut levels = vec![None];
+  if cfg!(feature = "compression") { levels.push(Some(3)) }
+
+  for level in levels {
+    let mux = |delta_filter, pcm_crc| {
+      let opts = MuxOptions { level, delta_filter, pcm_crc, silence_rle: pcm_crc,
+                              index_interval: Some(10_000), ..Default::default() };
+      let mut rkout = mux_with_options(Vec::new(), h, opts).unwrap();
+      for c in pcm.chunks(3001) { rkout.write_all(c).unwrap() }
+      rkout.finish().unwrap()
+    };
+
+    let (plain, delta) = (mux(false, false), mux(true, false));
+    assert!(decode_from_slice(&delta).unwrap().1 == pcm);
+    assert!(decode_from_slice(&mux(true, true)).unwrap().1 == pcm);
+    if level.is_some() { assert!(delta.len() < plain.len() * 3 / 4) }
+
+    // back to the middle, which has to add up from the start.
+    let mut rkin = demux_seekable(Cursor::new(delta), Default::default()).unwrap();
+    let mut out = Vec::new();
+    rkin.read_to_end(&mut out).unwrap();
+    rkin.seek_to_sample(25_000).unwrap();
+    out.clear();
+    rkin.read_to_end(&mut out).unwrap();
+    assert!(out == pcm[100_000..]);
+  }
+
+  // floats go through untouched, and unmarked.
+  let h = Hdr { format: Fmt::Float32, ..h };
+  let pcm = generate_tone(441.7, &h, 1000);
+  let opts = MuxOptions { delta_filter: true, ..Default::default() };
+  let mut rkout = mux_with_options(Vec::new(), h, opts).unwrap();
+  rkout.write_all(&pcm).unwrap();
+  assert!(rkout.finish().unwrap() == encode_to_vec(h, &pcm, None).unwrap());
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn rkpi2_auto_store() {
+  let h = Hdr { format: Fmt::Int16, rate: 44100, channels: 2, frames: None };
+  let mut x = 7u32;
+  let noise: Vec<u8> = (0..3 << 20).map(|_| {
+    x ^= x << 13; x ^= x >> 17; x ^= x << 5; x as u8
+  }).collect();
+  let tone = utils::testutil::generate_tone(441.0, &h, 100_000);
+
+  let opts = MuxOptions { level: Some(3), auto_store: true, ..Default::default() };
+  for &(pcm, compressed) in &[(&noise[..], false), (&noise[..5000], false),
+                              (&tone[..], true)] {
+    let mut rkout = mux_with_options(Vec::new(), h, opts).unwrap();
+    for c in pcm.chunks(100_001) { rkout.write_all(c).unwrap() }
+    let file = rkout.finish().unwrap();
+
+    assert!(demux(&file[..]).unwrap().compressed() == compressed);
+    assert!(decode_from_slice(&file).unwrap().1 == pcm);
+  }
+
+  // the frame count goes in the header written late.
+  let mut rkout = mux_seekable(Cursor::new(Vec::new()), h,
+                               MuxOptions { backfill_frames: true, ..opts })
+    .unwrap();
+  rkout.write_all(&noise[..4000]).unwrap();
+  let file = rkout.finish().unwrap().into_inner();
+  assert!(frame_count(&file[..]).unwrap() == Some(1000));
+  assert!(!demux(&file[..]).unwrap().compressed());
+}
+
+#[test]
+fn rkpi2_remux() {
+  let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 1, frames: Some(500) };
+  let pcm: Vec<u8> = (0..1000u32).map(|i| (i * 13) as u8).collect();
+  let level = if cfg!(feature = "compression") { Some(3) } else { None };
+  let opts = MuxOptions { level, ..Default::default() };
+
+  let mut rkout = mux_with_tags(Vec::new(), h, opts, &[("title", "old")]).unwrap();
+  rkout.write_all(&pcm).unwrap();
+  let file = rkout.finish().unwrap();
+  let payload = |f: &[u8]| f[parse_header(f).unwrap().2..].to_vec();
+
+  let tags = vec![("title".to_string(), "new".to_string()),
+                  ("artist".to_string(), "someone".to_string())];
+  let mut out = Vec::new();
+  assert!(remux(&file[..], &mut out, Some(tags.clone())).unwrap() == h);
+  assert!(read_tags(&out[..]).unwrap() == tags);
+  assert!(payload(&out) == payload(&file));
+  assert!(decode_from_slice(&out).unwrap() == (h, pcm.clone()));
+
+  // kept as they are, then dropped.
+  let mut kept = Vec::new();
+  remux(&file[..], &mut kept, None).unwrap();
+  assert!(kept == file);
+
+  let mut bare = Vec::new();
+  remux(&file[..], &mut bare, Some(Vec::new())).unwrap();
+  assert!(read_tags(&bare[..]).unwrap().is_empty());
+  assert!(payload(&bare) == payload(&file) && bare.len() < file.len());
+}
+
+#[test]
+fn rkpi2_loopback_matrix() {
+  use utils::testutil::generate_tone;
+
+  let levels = utils::testutil::levels();
+
+  // one bit for each switch: frame count, Zstd checksum, PCM CRC
+  // and silence RLE.
+  let cases = levels.iter()
+    .flat_map(|&l| Fmt::all().iter().map(move |&f| (l, f)))
+    .flat_map(|(l, f)| [1u8, 2, 8].iter().map(move |&c| (l, f, c)))
+    .flat_map(|(l, f, c)| (0..16u8).map(move |b| (l, f, c, b)));
+
+  // a few hundred frames of tone and silence each, so the whole
+  // matrix still runs in a blink.
+  for (level, format, channels, bits) in cases {
+    let bit = |n: u8| bits >> n & 1 == 1;
+    let h = Hdr { format,
+                  rate: 32000,
+                  channels,
+                  frames: if bit(0) { Some(300) } else { None } };
+    let opts = MuxOptions { level, zstd_checksum: bit(1) && level.is_some(),
+                            pcm_crc: bit(2), silence_rle: bit(3),
+                            ..Default::default() };
+
+    let mut pcm = generate_tone(1000.0, &h, 200);
+    pcm.resize(300 * h.block_align(), 0);
+
+    let mut rkout = mux_with_options(Vec::new(), h, opts).unwrap();
+    rkout.write_all(&pcm).unwrap();
+    let file = rkout.finish().unwrap();
+
+    let mut rkin = demux(&file[..]).unwrap();
+    let mut out = Vec::new();
+    rkin.read_to_end(&mut out).unwrap();
+    assert!(*rkin.header() == h && out == pcm, "{:?} {:?}", h, opts);
+  }
+}
+
+#[test]
+fn rkpi2_read_tags() {
+  let h = Hdr { format: Fmt::Int8,
+                rate: 8000,
+                channels: 1,
+                frames: Some(3) };
+  let tags = [("title", "Tönë"), ("artist", ""), ("artist", "b")];
+
+  let mut levels = vec![None];
+  if cfg!(feature = "compression") { levels.push(Some(3)) }
+
+  for level in levels {
+    let opts = MuxOptions { level, ..Default::default() };
+    let mut rkout = mux_with_tags(Vec::new(), h, opts, &tags).unwrap();
+    rkout.write_all(&[1, 2, 3]).unwrap();
+    let file = rkout.finish().unwrap();
+
+    let got = read_tags(&file[..]).unwrap();
+    assert!(got.len() == 3);
+    assert!(got.iter().zip(&tags).all(|((k, v), (x, y))| k == x && v == y));
+
+    // and the payload is still where demux expects it.
+    let mut out = Vec::new();
+    let mut rkin = demux(&file[..]).unwrap();
+    rkin.read_to_end(&mut out).unwrap();
+    assert!(*rkin.header() == h && out == [1, 2, 3]);
+  }
+
+  let file = encode_to_vec(h, &[1, 2, 3], None).unwrap();
+  assert!(read_tags(&file[..]).unwrap().is_empty());
+
+  let footer = MuxOptions { footer: true, ..Default::default() };
+  assert!(matches!(mux_with_tags(Vec::new(), h, footer, &tags),
+                   Err(RErr::Tags)));
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn rkpi2_seek_indexed() {
+  let h = Hdr { format: Fmt::Int16,
+                rate: 8000,
+                channels: 2,
+                frames: None };
+  let pcm: Vec<u8> = (0..16000u32).map(|i| ((i * 31) >> 4) as u8).collect();
+
+  for silence_rle in [false, true] {
+    let opts = MuxOptions { level: Some(3), silence_rle, pcm_crc: true,
+                            ..Default::default() };
+    let mut rkout = mux_with_options(Vec::new(), h, opts).unwrap();
+    rkout.write_all(&pcm[..8002]).unwrap();
+    rkout.flush_frame().unwrap();
+    rkout.write_all(&pcm[8002..]).unwrap();
+
+    let index = rkout.seek_index().clone();
+    // the partial PCM frame is held back by the RLE pass.
+    let start = if silence_rle { 8000 } else { 8002 };
+    assert!(index.points().len() == 1 && index.points()[0].0 == start);
+    let mut file = rkout.finish().unwrap();
+
+    let mut rkin = demux_indexed(Cursor::new(file.clone()), index.clone())
+      .unwrap();
+    let mut out = Vec::new();
+    rkin.read_to_end(&mut out).unwrap();
+    assert!(out == pcm);
+
+    // back into the second frame, then the first.
+    rkin.seek_to_sample(3000).unwrap();
+    assert!(rkin.position_frames() == 3000);
+    out.clear();
+    rkin.read_to_end(&mut out).unwrap();
+    assert!(out[..] == pcm[12000..]);
+
+    rkin.seek_to_sample(10).unwrap();
+    let mut s = [0u8; 8];
+    rkin.read_exact(&mut s).unwrap();
+    assert!(s == pcm[40..48]);
+
+    // without an index, only forwards.
+    let mut rkin = demux(Cursor::new(file.clone())).unwrap();
+    rkin.seek_to_sample(2001).unwrap();
+    rkin.read_exact(&mut s).unwrap();
+    assert!(s == pcm[8004..8012]);
+    assert!(matches!(rkin.seek_to_sample(10), Err(RErr::Range)));
+
+    // the second frame decodes on its own, without the first.
+    file[4 + 10] ^= 0xff;
+    let mut rkin = demux_indexed(Cursor::new(file), index).unwrap();
+    rkin.seek_to_sample(2001).unwrap();
+    rkin.read_exact(&mut s).unwrap();
+    assert!(s == pcm[8004..8012]);
+  }
+}
@@ -0,0 +1,101 @@
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr;
+
+use crate::{Hdr, RErr};
+use crate::header::{parse_complete, Coding, START_CODE};
+
+/// Read-only mapping of a whole file, derefs to its bytes and
+/// unmaps them when dropped.
+pub struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// the mapping is private and read-only, nothing aliases it mutably.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    fn map(f: &File) -> io::Result<Mmap> {
+        let len = f.metadata()?.len() as usize;
+
+        // mapping nothing is an error for mmap.
+        if len == 0 { return Ok(Mmap { ptr: ptr::null_mut(), len }) }
+
+        let ptr = unsafe {
+            libc::mmap(ptr::null_mut(), len, libc::PROT_READ,
+                       libc::MAP_PRIVATE, f.as_raw_fd(), 0)
+        };
+
+        if ptr == libc::MAP_FAILED { return Err(io::Error::last_os_error()) }
+        Ok(Mmap { ptr, len })
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self.len {
+            0 => &[],
+            _ => unsafe { std::slice::from_raw_parts(self.ptr as *const u8,
+                                                     self.len) }
+        }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe { libc::munmap(self.ptr, self.len); }
+        }
+    }
+}
+
+/// Map the RKPI2 file at `path` into memory, returning its
+/// header, the mapping and the offset its PCM data starts at,
+/// so samples can be sliced out of the mapping without being
+/// copied. Only plain PCM payloads can be used that way,
+/// others fail with `RErr::Codec`, `decode_from_slice` can be
+/// given the mapping for those.
+///
+/// The mapping reflects the file as it is on disk: if another
+/// process writes to it while mapped, the bytes change under
+/// the slices taken from it, and if it's truncated, touching
+/// the pages past its new end kills the process with `SIGBUS`.
+/// Only map files nothing else is going to modify.
+pub fn demux_mmap<P: AsRef<Path>>(path: P) -> Result<(Hdr, Mmap, usize), RErr> {
+    let f = File::open(path).map_err(|_| RErr::IO)?;
+    let map = Mmap::map(&f).map_err(|_| RErr::IO)?;
+
+    let (h, coding, off) = parse_complete(&map, START_CODE)?;
+    if coding != Coding::default() { return Err(RErr::Codec) }
+
+    Ok((h, map, off))
+}
+
+#[test]
+fn mmap_matches_demux() {
+    use std::io::Read;
+    use crate::{demux, encode_to_vec, Fmt};
+
+    let h = Hdr { format: Fmt::Int32, rate: 96000, channels: 2,
+                  frames: Some(250) };
+    let pcm: Vec<u8> = (0..2000u32).map(|i| (i % 253) as u8).collect();
+    let file = encode_to_vec(h, &pcm, None).unwrap();
+
+    let path = std::env::temp_dir()
+        .join(format!("rkpi2-mmap-{}.rk", std::process::id()));
+    std::fs::write(&path, &file).unwrap();
+
+    let (oh, map, off) = demux_mmap(&path).unwrap();
+    let mut out = Vec::new();
+    demux(&file[..]).unwrap().read_to_end(&mut out).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(oh == h && map[off..] == out[..]);
+}
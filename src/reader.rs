@@ -0,0 +1,1149 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Chain, Cursor, Read, Seek, SeekFrom};
+#[cfg(feature = "compression")]
+use std::mem;
+#[cfg(feature = "compression")]
+use zstd::stream::{raw::{Decoder, DParameter}, zio};
+
+use crate::{Codec, DemuxOptions, Fmt, Hdr, RErr, SeekIndex, Strictness};
+use crate::header::Coding;
+use crate::utils::conv::convert;
+use crate::utils::crc::Trailer;
+use crate::utils::delta::Delta;
+use crate::utils::rle::Unrle;
+use crate::utils::sample::Sample;
+
+/// What's left of the input after the header, with the bytes
+/// peeked while demuxing put back in front of it.
+pub(crate) type Payload<R> = Chain<Cursor<Vec<u8>>, R>;
+
+/// PCM source returned by `demux`, reads the payload after
+/// the header, decompressing it if needed. Reads are buffered,
+/// so `BufRead` can be used to go through the PCM data.
+pub struct RkReader<R: Read> {
+    src: BufReader<Decoded<R>>,
+    hdr: Hdr,
+    /// Bytes of PCM data read so far.
+    pos: u64,
+    /// Fail at the end of a payload that isn't frame-aligned.
+    strict: bool,
+    /// To set the source up again after seeking.
+    coding: Coding,
+    opts: DemuxOptions,
+    /// Where the payload starts in the underlying reader, and
+    /// its frame index, for `demux_indexed` streams.
+    seek: Option<(u64, SeekIndex)>,
+    /// Reserved format code read past, see `unknown_format`.
+    unknown: Option<u8>,
+    /// PCM data handed back by `fill_or_keep`, read before the
+    /// source again.
+    held: Vec<u8>,
+}
+
+/// Payload as the codec has it, dispatched with a `match` on
+/// every read rather than through a boxed `dyn Read`.
+enum Source<R: Read> {
+    Raw(Payload<R>),
+    #[cfg(feature = "compression")]
+    Zstd(LazyDecoder<R>),
+    /// Only seen while `seek_to_sample` is setting up a new one.
+    Detached,
+}
+
+/// PCM data out of the codec, with silent runs expanded if the
+/// payload has them collapsed, differences added back up if it
+/// has them, and checked against its CRC if it's followed by one.
+struct Decoded<R: Read> {
+    src: Source<R>,
+    rle: Option<Unrle>,
+    delta: Option<Delta>,
+    crc: Option<Trailer>,
+}
+
+/// Zstd decoder set up on the first read, so demuxing just to
+/// look at the header doesn't pay for the decoder's state.
+#[cfg(feature = "compression")]
+enum LazyDecoder<R: Read> {
+    /// Payload not read yet, with the window log to allow and
+    /// the size of the buffer to read it through.
+    Pending(Payload<R>, u32, usize),
+    Ready(zio::Reader<BufReader<Payload<R>>, Decoder>),
+    /// Only seen while going from `Pending` to `Ready`.
+    Poisoned,
+}
+
+/// Largest window the linked Zstd can decode, which is also
+/// the largest any encoder can produce.
+#[cfg(all(feature = "compression", target_pointer_width = "64"))]
+const WINDOW_LOG_MAX: u32 = zstd_safe::WINDOWLOG_MAX_64;
+#[cfg(all(feature = "compression", not(target_pointer_width = "64")))]
+const WINDOW_LOG_MAX: u32 = zstd_safe::WINDOWLOG_MAX_32;
+
+#[cfg(feature = "compression")]
+impl<R: Read> LazyDecoder<R> {
+    fn new(r: Payload<R>, opts: &DemuxOptions) -> Self {
+        LazyDecoder::Pending(r, opts.window_log_max.unwrap_or(WINDOW_LOG_MAX),
+                             opts.read_buffer_size
+                                 .unwrap_or_else(zstd_safe::dstream_in_size))
+    }
+
+    fn into_inner(self) -> Payload<R> {
+        match self {
+            LazyDecoder::Pending(r, ..) => r,
+            LazyDecoder::Ready(d) => d.into_inner().into_inner(),
+            LazyDecoder::Poisoned => unreachable!()
+        }
+    }
+}
+
+#[cfg(feature = "compression")]
+impl<R: Read> Read for LazyDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let LazyDecoder::Pending(_, wlog, _) = *self {
+            let mut d = Decoder::new()?;
+            d.set_parameter(DParameter::WindowLogMax(wlog))?;
+
+            if let LazyDecoder::Pending(r, _, cap) =
+                mem::replace(self, LazyDecoder::Poisoned) {
+                let r = BufReader::with_capacity(cap.max(1), r);
+                *self = LazyDecoder::Ready(zio::Reader::new(r, d));
+            }
+        }
+
+        match self {
+            LazyDecoder::Ready(d) => d.read(buf),
+            _ => unreachable!()
+        }
+    }
+}
+
+impl<R: Read> RkReader<R> {
+    pub(crate) fn new(r: Payload<R>, hdr: Hdr, c: Coding,
+                      opts: DemuxOptions) -> Result<Self, RErr> {
+        Ok(RkReader { src: BufReader::new(decoded(r, c, &hdr, &opts, true)?), hdr,
+                      pos: 0, strict: opts.strictness == Strictness::Strict,
+                      coding: c, opts, seek: None, unknown: None,
+                      held: Vec::new() })
+    }
+
+    pub(crate) fn with_index(mut self, at: u64, index: SeekIndex) -> Self {
+        self.seek = Some((at, index));
+        self
+    }
+
+    pub(crate) fn with_unknown_format(mut self, code: Option<u8>) -> Self {
+        self.unknown = code;
+        self
+    }
+
+    /// Reserved format code of the stream, read past as `Int8`
+    /// with `UnknownFormat::Passthrough`. `None` for every stream
+    /// whose header has a real format.
+    ///
+    /// With a code, the size of a sample isn't known, so only the
+    /// bytes can be read, through `Read` and `BufRead`. Everything
+    /// going by samples or frames fails with `RErr::Format`, and
+    /// the counts that can't fail come out as zero or `None`.
+    pub fn unknown_format(&self) -> Option<u8> {
+        self.unknown
+    }
+
+    /// Fail with `RErr::Format` if the size of a sample isn't known.
+    fn known_format(&self) -> Result<(), RErr> {
+        match self.unknown {
+            Some(_) => Err(RErr::Format),
+            None => Ok(())
+        }
+    }
+
+    /// Header parsed from the stream.
+    pub fn header(&self) -> &Hdr {
+        &self.hdr
+    }
+
+    /// Copy of the header, to keep around after the reader is
+    /// gone, the same as `*r.header()`.
+    pub fn header_owned(&self) -> Hdr {
+        self.hdr
+    }
+
+    /// Index `seek_to_sample` goes by, given to `demux_indexed` or
+    /// read by `demux_seekable`, `None` if it only seeks forward.
+    pub fn seek_index(&self) -> Option<&SeekIndex> {
+        self.seek.as_ref().map(|s| &s.1)
+    }
+
+    /// Give back the reader the stream was demuxed from, which
+    /// for an uncompressed stream is positioned right after the
+    /// last byte read, so reading can go on past the payload
+    /// (e.g. into the rest of a container). Bytes pulled in by
+    /// `fill_buf` but not consumed are lost though.
+    ///
+    /// The Zstd decoder reads ahead of what it has decoded, so
+    /// for compressed streams the position isn't meaningful.
+    pub fn into_inner(self) -> R {
+        self.src.into_inner().into_inner()
+    }
+
+    /// Size of the decoded payload in bytes, if the header
+    /// carries a frame count, saturating at `u64::MAX`. The
+    /// count comes from the stream, so don't trust it blindly.
+    pub fn decoded_size_hint(&self) -> Option<u64> {
+        self.known_format().ok()?;
+        let ba = self.hdr.block_align() as u64;
+        self.hdr.frames.map(|n| n.saturating_mul(ba))
+    }
+
+    /// Codec the payload is coded with.
+    pub fn codec(&self) -> Codec {
+        self.coding.codec
+    }
+
+    /// Whether the payload is compressed.
+    pub fn compressed(&self) -> bool {
+        self.codec() != Codec::None
+    }
+
+    /// Map a read error to the `RErr` it stands for, see `read_err`.
+    pub(crate) fn rerr(&self, e: io::Error) -> RErr {
+        RErr::from(self.read_err(e))
+    }
+
+    /// Error of a read from the source, with a decode error
+    /// wrapping the `RErr` it stands for, so that `RErr::from`
+    /// gets it back for those reading through `Read`: the Zstd
+    /// decoder reports bad input with `ErrorKind::Other` and a
+    /// payload cut off partway through a frame with `UnexpectedEof`.
+    /// Other errors, `WouldBlock` among them, go through as they are.
+    fn read_err(&self, e: io::Error) -> io::Error {
+        if e.get_ref().is_some_and(|e| e.is::<RErr>()) { return e }
+
+        match e.kind() {
+            io::ErrorKind::Other if self.compressed() => RErr::Decompress.into(),
+            io::ErrorKind::UnexpectedEof => RErr::Truncated.into(),
+            _ => e
+        }
+    }
+
+    /// `fill` from the reader, handing the bytes it got before an
+    /// error back to it, so that a read retried after `WouldBlock`
+    /// starts from them again.
+    fn fill_or_keep(&mut self, buf: &mut [u8]) -> Result<usize, RErr> {
+        let mut n = 0;
+        fill_at(self, buf, &mut n).map_err(|e| {
+            self.unread(&buf[..n]);
+            self.rerr(e)
+        })?;
+        Ok(n)
+    }
+
+    /// Put `pcm`, just read, back in front of what's left.
+    fn unread(&mut self, pcm: &[u8]) {
+        self.held.splice(..0, pcm.iter().copied());
+        self.pos -= pcm.len() as u64;
+    }
+
+    /// Bytes left to read before `max_decompressed_bytes`.
+    fn left(&self) -> Option<u64> {
+        self.opts.max_decompressed_bytes.map(|m| m.saturating_sub(self.pos))
+    }
+
+    /// Frames read so far, counting only whole ones: a frame
+    /// read partway isn't until the rest of it is read.
+    pub fn position_frames(&self) -> u64 {
+        if self.unknown.is_some() { return 0 }
+        self.hdr.frames_in(self.pos)
+    }
+
+    /// Frames left to read, going by the header's frame count,
+    /// or `None` without one. The Zstd content size isn't used,
+    /// as it can't be known without reading into the payload.
+    pub fn frames_remaining(&self) -> Option<u64> {
+        self.known_format().ok()?;
+        let read = self.hdr.frames_in(self.pos);
+        self.hdr.frames.map(|n| n.saturating_sub(read))
+    }
+
+    /// Whether the payload has been read to its end, as opposed
+    /// to just having nothing buffered. Reads ahead if needed,
+    /// blocking until more data or the EOF shows up (for a Zstd
+    /// payload, until the decoder yields something or hits the
+    /// end of its input), but doesn't consume anything. A broken
+    /// stream fails here like it would on a read.
+    ///
+    /// What this reads ahead of an uncompressed payload is lost
+    /// to `into_inner`, as with `fill_buf`.
+    pub fn is_eof(&mut self) -> Result<bool, RErr> {
+        loop {
+            match self.src.fill_buf() {
+                Ok(b) => return Ok(b.is_empty()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(self.rerr(e))
+            }
+        }
+    }
+
+    /// Read and throw away the next `n` frames, returning how
+    /// many were skipped, which is less than `n` at the EOF.
+    pub fn skip_frames(&mut self, n: u64) -> Result<u64, RErr> {
+        self.known_format()?;
+        // more bytes than a u64 holds is as good as to the EOF.
+        let ba = self.hdr.block_align() as u64;
+        let bytes = n.saturating_mul(ba);
+        let skipped = io::copy(&mut self.by_ref().take(bytes), &mut io::sink())
+            .map_err(|e| self.rerr(e))?;
+
+        Ok(skipped / ba)
+    }
+
+    /// PCM data of the frames in `[start, end)`, counted from the
+    /// start of the payload, with `end` clamped to the EOF. The
+    /// frames before `start` are skipped by reading through them,
+    /// so `start` can't be behind what was already read, in which
+    /// case, or if `start > end`, `RErr::Range` is returned. See
+    /// `seek_slice` for going back on seekable streams.
+    pub fn slice(&mut self, start: u64, end: u64) -> Result<Vec<u8>, RErr> {
+        self.known_format()?;
+        let ba = self.hdr.block_align() as u64;
+        let cur = self.pos.div_ceil(ba);
+        if start > end || start < cur { return Err(RErr::Range) }
+
+        // realign to a frame boundary after any partial read.
+        let mut skip = vec![0u8; (cur * ba - self.pos) as usize];
+        fill(self, &mut skip).map_err(|e| self.rerr(e))?;
+        self.skip_frames(start - cur)?;
+        self.read_frames(end - start)
+    }
+
+    /// PCM data of the next `n` whole frames, or of what's left.
+    /// What was read before an error is handed back, as with
+    /// `fill_or_keep`.
+    fn read_frames(&mut self, n: u64) -> Result<Vec<u8>, RErr> {
+        let ba = self.hdr.block_align() as u64;
+        let mut pcm = Vec::new();
+        if let Err(e) = self.by_ref().take(n.saturating_mul(ba))
+                            .read_to_end(&mut pcm) {
+            self.unread(&pcm);
+            return Err(self.rerr(e))
+        }
+        pcm.truncate(pcm.len() / ba as usize * ba as usize);
+
+        Ok(pcm)
+    }
+
+    /// PCM data of the next `secs` seconds of audio, rounded down
+    /// to whole frames, or of what's left if the stream ends before.
+    /// Fails with `RErr::Range` if `secs` is negative or NaN.
+    pub fn take_secs(&mut self, secs: f64) -> Result<Vec<u8>, RErr> {
+        self.known_format()?;
+        if secs.is_nan() || secs < 0.0 { return Err(RErr::Range) }
+
+        self.read_frames((secs * self.hdr.rate as f64) as u64)
+    }
+
+    /// Read the next frame into the start of `buf`, returning its
+    /// size (`block_align`), or 0 at the EOF. Nothing is allocated,
+    /// so it suits a playback loop over a fixed buffer. Fails with
+    /// `RErr::Range` if `buf` can't hold a frame, and with
+    /// `RErr::Truncated` if the stream ends partway through one.
+    pub fn read_frame_into(&mut self, buf: &mut [u8]) -> Result<usize, RErr> {
+        self.known_format()?;
+        let ba = self.hdr.block_align();
+        if buf.len() < ba { return Err(RErr::Range) }
+
+        match self.fill_or_keep(&mut buf[..ba])? {
+            0 => Ok(0),
+            n if n == ba => Ok(n),
+            _ => Err(RErr::Truncated)
+        }
+    }
+
+    /// Read the rest of the stream as floats, one `Vec` per
+    /// channel. It goes through the PCM data a chunk at a time,
+    /// so only the output is held in full. A partial frame at the
+    /// end of the stream is dropped.
+    pub fn decode_all_planar_f32(&mut self) -> Result<Vec<Vec<f32>>, RErr> {
+        self.known_format()?;
+        let ch = self.hdr.channels as usize;
+        let mut planar = vec![Vec::new(); ch];
+        let mut buf = vec![0u8; self.hdr.block_align() << 12];
+        let mut floats = Vec::new();
+
+        loop {
+            let n = self.fill_or_keep(&mut buf)?;
+            if n == 0 { break }
+
+            floats.clear();
+            convert(self.hdr.format, Fmt::Float32, &buf[..n], &mut floats);
+            for f in floats.chunks_exact(4 * ch) {
+                for (p, s) in planar.iter_mut().zip(f.chunks_exact(4)) {
+                    p.push(f32::get(s))
+                }
+            }
+        }
+
+        Ok(planar)
+    }
+
+    /// Wrap into a reader yielding the samples converted to
+    /// `target`, as if the stream had been muxed with it. Reading
+    /// a stream of unknown format fails with `RErr::Format`.
+    pub fn convert_to(self, target: Fmt) -> ConvertingReader<R> {
+        let chunk = vec![0u8; 4096 * self.hdr.format.size()];
+        ConvertingReader { inner: self, target, chunk,
+                           pending: Vec::new(),
+                           out: Vec::new(), pos: 0 }
+    }
+
+    /// Read samples of the native type of the stream's format
+    /// until `s` is full or the stream ends, returning how many
+    /// were read, see `read_i8_samples`.
+    pub fn read_samples<T: Sample>(&mut self, s: &mut [T])
+        -> Result<usize, RErr> {
+        self.known_format()?;
+        if self.hdr.format != T::FMT { return Err(RErr::Format) }
+
+        // go through a buffer on the stack, a chunk of `s` at a time,
+        // what's been read so far counts if a later one would block.
+        let size = T::FMT.size();
+        let mut buf = [0u8; 4096];
+        let mut read = 0;
+        for c in s.chunks_mut(buf.len() / size) {
+            let want = c.len() * size;
+            let n = match self.fill_or_keep(&mut buf[..want]) {
+                Err(RErr::WouldBlock) if read > 0 => break,
+                r => r?
+            };
+
+            for (x, b) in c.iter_mut().zip(buf[..n].chunks_exact(size))
+            { *x = T::get(b) }
+
+            read += n / size;
+            if n < want { break }
+        }
+
+        Ok(read)
+    }
+
+    /// Read `Int8` samples until `s` is full or the stream ends,
+    /// returning how many were read. Fails with `RErr::Format`
+    /// if the stream has a different sampleformat. A partial
+    /// sample at the end of the stream is dropped. The other
+    /// typed readers below work the same way, they're all
+    /// `read_samples` for one type.
+    pub fn read_i8_samples(&mut self, s: &mut [i8])
+        -> Result<usize, RErr> {
+        self.read_samples(s)
+    }
+
+    /// Read little-endian `Int16` samples.
+    pub fn read_i16_samples(&mut self, s: &mut [i16])
+        -> Result<usize, RErr> {
+        self.read_samples(s)
+    }
+
+    /// Read little-endian `Int32` samples.
+    pub fn read_i32_samples(&mut self, s: &mut [i32])
+        -> Result<usize, RErr> {
+        self.read_samples(s)
+    }
+
+    /// Read little-endian `Int64` samples.
+    pub fn read_i64_samples(&mut self, s: &mut [i64])
+        -> Result<usize, RErr> {
+        self.read_samples(s)
+    }
+
+    /// Read little-endian `Float32` samples.
+    pub fn read_f32_samples(&mut self, s: &mut [f32])
+        -> Result<usize, RErr> {
+        self.read_samples(s)
+    }
+
+    /// Read little-endian `Float64` samples.
+    pub fn read_f64_samples(&mut self, s: &mut [f64])
+        -> Result<usize, RErr> {
+        self.read_samples(s)
+    }
+}
+
+impl<R: Read> Read for Source<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Source::Raw(r) => r.read(buf),
+            #[cfg(feature = "compression")]
+            Source::Zstd(d) => d.read(buf),
+            Source::Detached => unreachable!()
+        }
+    }
+}
+
+/// Set up the decoding of `r`, from the start of the payload
+/// if `start`, which the CRC can only be checked from.
+#[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+fn decoded<R: Read>(r: Payload<R>, c: Coding, h: &Hdr, opts: &DemuxOptions,
+                    start: bool) -> Result<Decoded<R>, RErr> {
+    let src = match c.codec {
+        #[cfg(feature = "compression")]
+        Codec::Zstd => Source::Zstd(LazyDecoder::new(r, opts)),
+        #[cfg(not(feature = "compression"))]
+        Codec::Zstd => return Err(RErr::CompressionUnsupported),
+        Codec::None => Source::Raw(r)
+    };
+
+    let rle = if c.rle { Some(Unrle::default()) } else { None };
+    let delta = match c.delta {
+        true => Some(Delta::new(h.format.size(), h.channels as usize)),
+        false => None
+    };
+    let crc = match (c.crc, start) {
+        (false, _) => None,
+        (true, true) => Some(Trailer::default()),
+        (true, false) => Some(Trailer::unchecked())
+    };
+
+    Ok(Decoded { src, rle, delta, crc })
+}
+
+impl<R: Read> Decoded<R> {
+    fn into_inner(self) -> R {
+        match self.src {
+            Source::Raw(r) => r.into_inner().1,
+            #[cfg(feature = "compression")]
+            Source::Zstd(d) => d.into_inner().into_inner().1,
+            Source::Detached => unreachable!()
+        }
+    }
+}
+
+impl<R: Read + Seek> RkReader<R> {
+    /// Go to frame `frame`, counted from the start of the payload,
+    /// so that it's the next one read. Past the EOF, the next read
+    /// just ends the stream.
+    ///
+    /// A stream opened with `demux_indexed`, or `demux_seekable`
+    /// finding an index, can be seeked anywhere:
+    /// an uncompressed payload directly, a compressed one by going
+    /// to the start of the Zstd frame holding `frame` in its index
+    /// and decoding from there. Otherwise seeking only goes forward
+    /// by reading through, and seeking back fails with `RErr::Range`,
+    /// as it does for compressed streams muxed without
+    /// `flush_frame`, whose index is empty.
+    ///
+    /// A stream with a PCM CRC (`MuxOptions::pcm_crc`) can't be
+    /// checked against it anymore once it's been seeked back.
+    pub fn seek_to_sample(&mut self, frame: u64) -> Result<(), RErr> {
+        self.known_format()?;
+        let ba = self.hdr.block_align() as u64;
+        let to = frame.saturating_mul(ba);
+        let plain = self.coding.codec == Codec::None && !self.coding.rle;
+
+        // differences only add up from the start of the payload.
+        let (pcm, payload, at) = match &self.seek {
+            Some((at, _)) if self.coding.delta => (0, 0, *at),
+            Some((at, _)) if plain => (to, to, *at),
+            Some((at, idx)) => { let (p, o) = idx.lookup(to); (p, o, *at) },
+            None if to < self.pos => return Err(RErr::Range),
+            None => return self.skip_bytes(to - self.pos)
+        };
+
+        // going forward within the same frame, just read through.
+        if pcm <= self.pos && self.pos <= to {
+            return self.skip_bytes(to - self.pos)
+        }
+
+        let old = std::mem::replace(self.src.get_mut(), Decoded {
+            src: Source::Detached, rle: None, delta: None, crc: None });
+        let mut r = old.into_inner();
+        let seeked = r.seek(SeekFrom::Start(at.saturating_add(payload)));
+
+        // the coding was set up once already, so this can't fail.
+        let payload = Cursor::new(Vec::new()).chain(r);
+        self.src = BufReader::new(decoded(payload, self.coding, &self.hdr,
+                                          &self.opts, pcm == 0)?);
+        self.pos = pcm;
+        self.held.clear();
+        if seeked.is_err() { return Err(RErr::IO) }
+
+        self.skip_bytes(to - pcm)
+    }
+
+    /// `slice`, going to `start` with `seek_to_sample`, so that
+    /// it can be behind what was already read wherever seeking
+    /// back can be done.
+    pub fn seek_slice(&mut self, start: u64, end: u64)
+        -> Result<Vec<u8>, RErr> {
+        if start > end { return Err(RErr::Range) }
+        self.seek_to_sample(start)?;
+        self.read_frames(end - start)
+    }
+
+    fn skip_bytes(&mut self, n: u64) -> Result<(), RErr> {
+        io::copy(&mut self.by_ref().take(n), &mut io::sink())
+            .map_err(|e| self.rerr(e))?;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Decoded<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (src, rle, delta) = (&mut self.src, &mut self.rle, &mut self.delta);
+        let mut next = |b: &mut [u8]| {
+            let n = match rle {
+                Some(rle) => rle.read(src, b)?,
+                None => src.read(b)?
+            };
+
+            if let Some(d) = delta { d.decode(&mut b[..n]) }
+            Ok(n)
+        };
+
+        match &mut self.crc {
+            Some(crc) => crc.read(&mut next, buf),
+            None => next(buf)
+        }
+    }
+}
+
+/// Shows where the reader is, never touching the source, so
+/// it needn't be `Debug` itself.
+impl<R: Read> fmt::Debug for RkReader<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RkReader")
+         .field("hdr", &self.hdr)
+         .field("compressed", &self.compressed())
+         .field("pos_frames", &self.position_frames())
+         .finish()
+    }
+}
+
+impl<R: Read> Read for RkReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.held.is_empty() {
+            let n = buf.len().min(self.held.len());
+            buf[..n].copy_from_slice(&self.held[..n]);
+            self.held.drain(..n);
+            self.pos += n as u64;
+            return Ok(n)
+        }
+
+        // one byte past the limit is enough to tell it's crossed.
+        let buf = match self.left() {
+            Some(l) => { let n = buf.len().min(l.saturating_add(1) as usize);
+                         &mut buf[..n] }
+            None => buf
+        };
+
+        // going around the buffer keeps an uncompressed source from
+        // being read past what was asked for, see `into_inner`.
+        let bypass = self.src.buffer().is_empty();
+        let n = match self.src.get_mut() {
+            Decoded { src: Source::Raw(r), rle: None, delta: None, crc: None }
+                if bypass
+                => r.read(buf),
+            _ => self.src.read(buf)
+        }.map_err(|e| self.read_err(e))?;
+
+        if n == 0 && !buf.is_empty() && self.strict
+           && !self.pos.is_multiple_of(self.hdr.block_align() as u64) {
+            return Err(RErr::Misaligned.into())
+        }
+        if self.left().is_some_and(|l| n as u64 > l) {
+            return Err(RErr::LimitExceeded.into())
+        }
+
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read> AsRef<Hdr> for RkReader<R> {
+    fn as_ref(&self) -> &Hdr {
+        &self.hdr
+    }
+}
+
+impl<R: Read> BufRead for RkReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if !self.held.is_empty() { return Ok(&self.held) }
+
+        let left = self.left();
+        if let Err(e) = self.src.fill_buf() { return Err(self.read_err(e)) }
+        let b = self.src.buffer();
+
+        match left {
+            Some(0) if !b.is_empty() => Err(RErr::LimitExceeded.into()),
+            Some(l) if b.len() as u64 > l => Ok(&b[..l as usize]),
+            _ => Ok(b)
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt as u64;
+        let held = amt.min(self.held.len());
+        self.held.drain(..held);
+        self.src.consume(amt - held)
+    }
+}
+
+/// Reader returned by `RkReader::convert_to`, converting the
+/// samples one chunk at a time as they're read.
+pub struct ConvertingReader<R: Read> {
+    inner: RkReader<R>,
+    target: Fmt,
+    /// Scratch for reading from `inner`, kept across reads.
+    chunk: Vec<u8>,
+    /// Trailing bytes of a sample split across two reads.
+    pending: Vec<u8>,
+    out: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> ConvertingReader<R> {
+    /// Header of the stream, with the format being converted to.
+    pub fn header(&self) -> Hdr {
+        Hdr { format: self.target, ..self.inner.hdr }
+    }
+}
+
+impl<R: Read> Read for ConvertingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.known_format()?;
+        let from = self.inner.hdr.format;
+
+        while self.pos == self.out.len() {
+            let n = self.inner.read(&mut self.chunk)?;
+            if n == 0 { return Ok(0) }
+
+            self.pending.extend_from_slice(&self.chunk[..n]);
+            let whole = self.pending.len() / from.size() * from.size();
+
+            self.out.clear();
+            self.pos = 0;
+            convert(from, self.target, &self.pending[..whole], &mut self.out);
+            self.pending.drain(..whole);
+        }
+
+        let n = buf.len().min(self.out.len() - self.pos);
+        buf[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+/// Read until `buf` is full or the EOF, whichever comes first.
+pub(crate) fn fill<R: Read>(r: &mut R, buf: &mut [u8])
+    -> io::Result<usize> {
+    let mut n = 0;
+    fill_at(r, buf, &mut n)?;
+    Ok(n)
+}
+
+/// Same as `fill`, from `*at` on, keeping `*at` past the bytes
+/// read so far even if it fails, so the caller can go on from
+/// there.
+pub(crate) fn fill_at<R: Read>(r: &mut R, buf: &mut [u8], at: &mut usize)
+    -> io::Result<()> {
+    while *at < buf.len() {
+        match r.read(&mut buf[*at..]) {
+            Ok(0) => break,
+            Ok(k) => *at += k,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e)
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn typed_samples_roundtrip() {
+    use crate::{demux, mux, Fmt};
+
+    fn file(format: Fmt) -> crate::RkWriter<Vec<u8>> {
+        let h = Hdr { format, rate: 8000, channels: 2,
+                      ..Default::default() };
+        mux(Vec::new(), h, None).unwrap()
+    }
+
+    fn open(w: crate::RkWriter<Vec<u8>>) -> RkReader<Cursor<Vec<u8>>> {
+        demux(Cursor::new(w.finish().unwrap())).unwrap()
+    }
+
+    let mut w = file(Fmt::Int8);
+    w.write_i8_samples(&[-128, 0, 127]).unwrap();
+    assert!(matches!(w.write_i16_samples(&[0]), Err(RErr::Format)));
+    let mut s = [0i8; 4];
+    assert!(open(w).read_i8_samples(&mut s).unwrap() == 3);
+    assert!(s[..3] == [-128, 0, 127]);
+
+    let mut w = file(Fmt::Int16);
+    w.write_i16_samples(&[i16::MIN, -1, i16::MAX]).unwrap();
+    let mut r = open(w);
+    let mut s = [0i16; 3];
+    assert!(matches!(r.read_i32_samples(&mut [0]), Err(RErr::Format)));
+    assert!(r.read_i16_samples(&mut s).unwrap() == 3);
+    assert!(s == [i16::MIN, -1, i16::MAX]);
+
+    let mut w = file(Fmt::Int32);
+    w.write_i32_samples(&[i32::MIN, 7, i32::MAX]).unwrap();
+    let mut s = [0i32; 3];
+    assert!(open(w).read_i32_samples(&mut s).unwrap() == 3);
+    assert!(s == [i32::MIN, 7, i32::MAX]);
+
+    let mut w = file(Fmt::Int64);
+    w.write_i64_samples(&[i64::MIN, 7, i64::MAX]).unwrap();
+    let mut s = [0i64; 3];
+    assert!(open(w).read_i64_samples(&mut s).unwrap() == 3);
+    assert!(s == [i64::MIN, 7, i64::MAX]);
+
+    let mut w = file(Fmt::Float32);
+    w.write_f32_samples(&[-1.0, 0.25, 1.0]).unwrap();
+    let mut s = [0f32; 3];
+    assert!(open(w).read_f32_samples(&mut s).unwrap() == 3);
+    assert!(s == [-1.0, 0.25, 1.0]);
+
+    let mut w = file(Fmt::Float64);
+    w.write_f64_samples(&[-1.0, 0.125, 1.0]).unwrap();
+    let mut s = [0f64; 3];
+    assert!(open(w).read_f64_samples(&mut s).unwrap() == 3);
+    assert!(s == [-1.0, 0.125, 1.0]);
+
+    // longer than a chunk read at a time.
+    let long: Vec<i16> = (0..3000).collect();
+    let mut w = file(Fmt::Int16);
+    w.write_i16_samples(&long).unwrap();
+    let mut s = vec![0i16; 4000];
+    assert!(open(w).read_i16_samples(&mut s).unwrap() == 3000);
+    assert!(s[..3000] == long[..]);
+}
+
+#[test]
+fn convert_int16_to_float32() {
+    use crate::{demux, mux};
+
+    let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 1,
+                  ..Default::default() };
+    let mut w = mux(Vec::new(), h, None).unwrap();
+    w.write_i16_samples(&[i16::MIN, 0, 16384]).unwrap();
+
+    let r = demux(Cursor::new(w.finish().unwrap())).unwrap();
+    let mut r = r.convert_to(Fmt::Float32);
+    assert!(r.header().format == Fmt::Float32);
+
+    let mut out = Vec::new();
+    r.read_to_end(&mut out).unwrap();
+
+    let s: Vec<f32> = out.chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+    assert!(s == [-1.0, 0.0, 0.5]);
+}
+
+#[test]
+fn bufread_over_payload() {
+    use crate::encode_to_vec;
+
+    let h = Hdr { format: Fmt::Int8, rate: 8000, channels: 1,
+                  ..Default::default() };
+    let mut r = crate::demux(Cursor::new(
+        encode_to_vec(h, &[1, 2, 3], None).unwrap())).unwrap();
+
+    assert!(r.fill_buf().unwrap().starts_with(&[1]));
+    r.consume(1);
+    assert!(r.fill_buf().unwrap() == [2, 3]);
+    r.consume(2);
+    assert!(r.fill_buf().unwrap().is_empty());
+}
+
+#[test]
+fn into_inner_after_partial_read() {
+    use crate::encode_to_vec;
+
+    let h = Hdr { format: Fmt::Int8, rate: 8000, channels: 1,
+                  ..Default::default() };
+    let file = encode_to_vec(h, &[1, 2, 3, 4, 5, 6], None).unwrap();
+
+    let mut r = crate::demux(Cursor::new(file)).unwrap();
+    let mut s = [0i8; 2];
+    r.read_i8_samples(&mut s).unwrap();
+
+    let mut c = r.into_inner();
+    assert!(c.position() == 4);
+
+    let mut rest = Vec::new();
+    c.read_to_end(&mut rest).unwrap();
+    assert!(rest == [3, 4, 5, 6]);
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn lazy_decoder_untouched() {
+    use crate::encode_to_vec;
+
+    let h = Hdr::default();
+    let file = encode_to_vec(h, &[9; 4096], Some(3)).unwrap();
+
+    // nothing past the header is pulled in until the first read.
+    let r = crate::demux(Cursor::new(&file)).unwrap();
+    assert!(r.into_inner().position() == 2);
+
+    let mut r = crate::demux(Cursor::new(&file)).unwrap();
+    let mut out = Vec::new();
+    r.read_to_end(&mut out).unwrap();
+    assert!(out == [9; 4096]);
+}
+
+#[test]
+fn eof_at_end_of_payload() {
+    use crate::encode_to_vec;
+
+    let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 1,
+                  ..Default::default() };
+    let mut levels = vec![None];
+    if cfg!(feature = "compression") { levels.push(Some(3)) }
+
+    for lev in levels {
+        let file = encode_to_vec(h, &[1, 0, 2, 0, 3, 0], lev).unwrap();
+        let mut r = crate::demux(Cursor::new(file)).unwrap();
+        let mut s = [0i16; 2];
+
+        assert!(!r.is_eof().unwrap());
+        r.read_i16_samples(&mut s).unwrap();
+        assert!(!r.is_eof().unwrap());
+        assert!(r.read_i16_samples(&mut s).unwrap() == 1 && s[0] == 3);
+        assert!(r.is_eof().unwrap());
+    }
+}
+
+#[test]
+fn frames_remaining_counts_down() {
+    use crate::encode_to_vec;
+
+    let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 2,
+                  frames: Some(4) };
+    let file = encode_to_vec(h, &[0; 16], None).unwrap();
+    let mut r = crate::demux(Cursor::new(file)).unwrap();
+
+    let mut s = [0i16; 3];
+    assert!(r.frames_remaining() == Some(4));
+    r.read_i16_samples(&mut s).unwrap();
+    assert!(r.frames_remaining() == Some(3));
+    r.read_i16_samples(&mut s).unwrap();
+    assert!(r.frames_remaining() == Some(1));
+    r.skip_frames(10).unwrap();
+    assert!(r.frames_remaining() == Some(0));
+
+    let h = Hdr { frames: None, ..h };
+    let file = encode_to_vec(h, &[0; 16], None).unwrap();
+    let r = crate::demux(Cursor::new(file)).unwrap();
+    assert!(r.frames_remaining().is_none());
+}
+
+#[test]
+fn header_as_ref() {
+    use crate::encode_to_vec;
+
+    fn rate_of<H: AsRef<Hdr>>(h: H) -> u32 { h.as_ref().rate }
+
+    let h = Hdr { rate: 12000, ..Default::default() };
+    let file = encode_to_vec(h, &[0; 8], None).unwrap();
+    let r = crate::demux(Cursor::new(file)).unwrap();
+
+    assert!(r.as_ref().rate == 12000);
+    assert!(rate_of(&r) == 12000 && rate_of(h) == 12000);
+
+    let owned = r.header_owned();
+    drop(r);
+    assert!(owned == h);
+}
+
+#[test]
+fn slice_frame_range() {
+    use crate::encode_to_vec;
+
+    let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 2,
+                  ..Default::default() };
+    let pcm: Vec<u8> = (0..40).collect();
+    let file = encode_to_vec(h, &pcm, None).unwrap();
+
+    let mut r = crate::demux(Cursor::new(&file)).unwrap();
+    assert!(r.slice(2, 5).unwrap() == pcm[8..20]);
+    assert!(matches!(r.slice(1, 3), Err(RErr::Range)));
+    assert!(matches!(r.slice(7, 6), Err(RErr::Range)));
+    assert!(r.slice(8, 100).unwrap() == pcm[32..]);
+
+    let mut r = crate::demux(Cursor::new(&file)).unwrap();
+    assert!(r.skip_frames(3).unwrap() == 3);
+    assert!(r.skip_frames(100).unwrap() == 7);
+
+    let mut r = crate::demux_seekable(Cursor::new(&file),
+                                     Default::default()).unwrap();
+    assert!(r.seek_slice(6, 8).unwrap() == pcm[24..32]);
+    assert!(r.seek_slice(1, 3).unwrap() == pcm[4..12]);
+    assert!(matches!(r.seek_slice(7, 6), Err(RErr::Range)));
+}
+
+#[test]
+fn take_first_half_second() {
+    use crate::encode_to_vec;
+
+    let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 2,
+                  frames: None };
+    let pcm: Vec<u8> = (0..8000 * 4).map(|i| i as u8).collect();
+    let file = encode_to_vec(h, &pcm, None).unwrap();
+    let mut r = crate::demux(&file[..]).unwrap();
+
+    let head = r.take_secs(0.5).unwrap();
+    assert!(head.len() == 4000 * 4 && head[..] == pcm[..4000 * 4]);
+
+    // only another half second is left.
+    assert!(r.take_secs(10.0).unwrap()[..] == pcm[4000 * 4..]);
+    assert!(r.take_secs(1.0).unwrap().is_empty());
+    assert!(matches!(r.take_secs(-1.0), Err(RErr::Range)));
+    assert!(matches!(r.take_secs(f64::NAN), Err(RErr::Range)));
+}
+
+#[test]
+fn position_in_frames() {
+    use crate::encode_to_vec;
+
+    let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 2,
+                  frames: None };
+    let file = encode_to_vec(h, &[0; 1000 * 4], None).unwrap();
+    let mut r = crate::demux(&file[..]).unwrap();
+
+    let mut buf = [0u8; 100 * 4];
+    r.read_exact(&mut buf).unwrap();
+    assert!(r.position_frames() == 100);
+
+    r.read_exact(&mut buf[..3]).unwrap();
+    assert!(r.position_frames() == 100);
+    r.fill_buf().unwrap();
+    r.consume(1);
+    assert!(r.position_frames() == 101);
+}
+
+#[test]
+fn huge_frame_counts_saturate() {
+    use crate::{encode_to_vec, max_encoded_size};
+
+    // counts a 32-bit usize couldn't hold in bytes, and that don't
+    // fit a u64 once multiplied by the frame size either.
+    let h = Hdr { format: Fmt::Float64, rate: 8000, channels: 8,
+                  frames: Some(u64::MAX) };
+    assert!(max_encoded_size(&h, u64::MAX, Some(3)) == u64::MAX);
+    assert!(max_encoded_size(&h, 1 << 40, None) == (1 << 46) + 12);
+
+    let mut r = crate::demux(Cursor::new(encode_to_vec(h, &[0; 64 * 3], None)
+                                          .unwrap())).unwrap();
+    assert!(r.decoded_size_hint() == Some(u64::MAX));
+    assert!(r.frames_remaining() == Some(u64::MAX));
+    assert!(r.skip_frames(1).unwrap() == 1);
+    assert!(r.slice(1, u64::MAX).unwrap().len() == 64 * 2);
+    assert!(r.skip_frames(u64::MAX).unwrap() == 0);
+    assert!(r.take_secs(f64::MAX).unwrap().is_empty());
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn tiny_read_buffer() {
+    use std::io::Read;
+    use crate::{demux_with_options, encode_to_vec, DemuxOptions};
+
+    let pcm: Vec<u8> = (0..5000u32).map(|i| (i * 7 % 256) as u8).collect();
+    let file = encode_to_vec(Hdr::default(), &pcm, Some(3)).unwrap();
+
+    for &n in [0, 1, 100].iter() {
+        let opts = DemuxOptions { read_buffer_size: Some(n),
+                                  ..Default::default() };
+        let mut out = Vec::new();
+        demux_with_options(&file[..], opts).unwrap()
+            .read_to_end(&mut out).unwrap();
+        assert!(out == pcm);
+    }
+}
+
+#[test]
+fn decode_planar_stereo() {
+    use crate::encode_to_vec;
+
+    let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 2,
+                  frames: None };
+    let s = [0i16, 16384, -16384, i16::MIN, 8192, -8192];
+    let pcm: Vec<u8> = s.iter().flat_map(|x| x.to_le_bytes()).collect();
+    let file = encode_to_vec(h, &pcm, None).unwrap();
+
+    let planar = crate::demux(&file[..]).unwrap()
+        .decode_all_planar_f32().unwrap();
+    assert!(planar == [vec![0.0, -0.5, 0.25], vec![0.5, -1.0, -0.25]]);
+}
+
+#[test]
+fn frames_into_fixed_buffer() {
+    use crate::encode_to_vec;
+
+    let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 2,
+                  frames: None };
+    let file = encode_to_vec(h, &[1, 2, 3, 4, 5, 6, 7, 8, 9], None).unwrap();
+    let mut r = crate::demux(&file[..]).unwrap();
+
+    let mut buf = [0u8; 6];
+    assert!(matches!(r.read_frame_into(&mut buf[..3]), Err(RErr::Range)));
+    assert!(r.read_frame_into(&mut buf).unwrap() == 4 && buf[..4] == [1, 2, 3, 4]);
+    assert!(r.read_frame_into(&mut buf).unwrap() == 4 && buf[..4] == [5, 6, 7, 8]);
+    assert!(matches!(r.read_frame_into(&mut buf), Err(RErr::Truncated)));
+    assert!(r.read_frame_into(&mut buf).unwrap() == 0);
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn truncated_zstd_frame() {
+    use crate::encode_to_vec;
+
+    let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 1,
+                  frames: None };
+    let pcm: Vec<u8> = (0..20000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let file = encode_to_vec(h, &pcm, Some(3)).unwrap();
+    let mut s = vec![0i16; pcm.len() / 2];
+
+    let mut r = crate::demux(&file[..file.len() - 10]).unwrap();
+    assert!(matches!(r.read_i16_samples(&mut s), Err(RErr::Truncated)));
+
+    // the reserved bit of the Zstd frame header set.
+    let mut bad = file.clone();
+    bad[2 + 4] |= 0x08;
+    let mut r = crate::demux(&bad[..]).unwrap();
+    assert!(matches!(r.read_i16_samples(&mut s), Err(RErr::Decompress)));
+
+    // the same through `Read`, and the slice API built on it.
+    let e = crate::demux(&bad[..]).unwrap().read_to_end(&mut Vec::new())
+        .unwrap_err();
+    assert!(matches!(RErr::from(e), RErr::Decompress));
+    assert!(matches!(crate::decode_from_slice(&bad), Err(RErr::Decompress)));
+    assert!(matches!(crate::decode_from_slice(&file[..file.len() - 10]),
+                     Err(RErr::Truncated)));
+}
+
+#[test]
+fn debug_without_source() {
+    use crate::encode_to_vec;
+
+    // a source that isn't `Debug` itself.
+    struct Opaque<'a>(&'a [u8]);
+    impl Read for Opaque<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    let h = Hdr { format: Fmt::Int16, rate: 22050, channels: 1,
+                  frames: None };
+    let file = encode_to_vec(h, &[0; 20], None).unwrap();
+    let mut r = crate::demux(Opaque(&file)).unwrap();
+    r.skip_frames(3).unwrap();
+
+    let s = format!("{:?}", r);
+    assert!(s.contains("Int16") && s.contains("22050"), "{}", s);
+    assert!(s.contains("compressed: false") && s.contains("pos_frames: 3"));
+}
@@ -0,0 +1,241 @@
+use std::io::{self, Read, Write};
+
+use crate::{mux, ConvertingReader, Fmt, Hdr, RErr, RkReader};
+use crate::header::SAMPLERATES;
+use crate::reader::fill_at;
+use crate::utils::conv::encode_f32;
+
+/// Supported samplerate closest to `rate`.
+pub fn nearest_rate(rate: u32) -> u32 {
+    *SAMPLERATES.iter()
+        .min_by_key(|&&s| (s as i64 - rate as i64).abs())
+        .unwrap()
+}
+
+/// Resample interleaved samples from `from` to `to`Hz with
+/// linear interpolation between neighbouring frames.
+///
+/// This is cheap, but it doesn't low-pass before decimating,
+/// so going down in rate aliases whatever is above the new
+/// Nyquist frequency, and it's slightly dull going up. Use a
+/// proper resampler for anything beyond previews and voice.
+///
+/// With no `channels` or a `from` rate of 0 there's nothing to
+/// go by, the samples come back unchanged.
+pub fn resample_to_supported(samples: &[f32], from: u32, to: u32,
+                             channels: usize) -> Vec<f32> {
+    if channels == 0 || from == 0 { return samples.to_vec() }
+    let frames = samples.len() / channels;
+    if from == to || frames == 0
+    { return samples[..frames * channels].to_vec() }
+
+    let len = (frames as u64 * to as u64 / from as u64) as usize;
+    let step = from as f64 / to as f64;
+    let mut out = Vec::with_capacity(len * channels);
+
+    for i in 0..len {
+        let pos = i as f64 * step;
+        let a = (pos as usize).min(frames - 1);
+        let b = (a + 1).min(frames - 1);
+        let t = (pos - a as f64) as f32;
+
+        for c in 0..channels {
+            let (x, y) = (samples[a * channels + c], samples[b * channels + c]);
+            out.push(x + (y - x) * t);
+        }
+    }
+
+    out
+}
+
+/// Mux float samples at any rate, resampling them to the
+/// nearest supported one first if `h.rate` isn't. `samples`
+/// are converted to `h.format`, and the header that was
+/// actually written is returned.
+pub fn mux_resampled<W: Write>(w: W, h: Hdr, samples: &[f32], lev: Option<u8>)
+    -> Result<Hdr, RErr> {
+    let channels = h.channels as usize;
+    if channels == 0 { return Err(RErr::Channels) }
+
+    let rate = nearest_rate(h.rate);
+    let samples = resample_to_supported(samples, h.rate, rate, channels);
+    let h = Hdr { rate,
+                  frames: h.frames.map(|_| (samples.len() / channels) as u64),
+                  ..h };
+
+    let mut out = Vec::new();
+    encode_f32(&samples, h.format, &mut out);
+
+    let mut rkout = mux(w, h, lev)?;
+    if rkout.write_all(&out).is_err() { return Err(RErr::IO) }
+
+    rkout.finish()?;
+    Ok(h)
+}
+
+impl<R: Read> RkReader<R> {
+    /// Read the stream as `target_fmt` samples at `target_rate`Hz,
+    /// which needn't be one of the supported rates: decoding,
+    /// conversion and resampling all happen lazily as it's read.
+    /// Fails with `RErr::Rate` if `target_rate` is zero, and with
+    /// `RErr::Format` for a stream of unknown format.
+    ///
+    /// Resampling interpolates linearly like `resample_to_supported`
+    /// and has the same aliasing, but it only ever holds a chunk
+    /// of 4096 frames, and that much latency, in memory.
+    pub fn to_target(self, target_rate: u32, target_fmt: Fmt)
+        -> Result<ResamplingReader<R>, RErr> {
+        if target_rate == 0 { return Err(RErr::Rate) }
+        if self.unknown_format().is_some() { return Err(RErr::Format) }
+
+        let hdr = *self.header();
+        Ok(ResamplingReader {
+            from: hdr.rate as u64,
+            to: target_rate as u64,
+            hdr: Hdr { format: target_fmt,
+                       rate: target_rate,
+                       frames: hdr.frames.map(|f| (f as u128 * target_rate as u128
+                                                   / hdr.rate as u128) as u64),
+                       ..hdr },
+            chunk: vec![0u8; 4096 * hdr.channels as usize * 4], filled: 0,
+            inner: self.convert_to(Fmt::Float32),
+            frames: Vec::new(), base: 0, next: 0, eof: false,
+            out: Vec::new(), pos: 0
+        })
+    }
+}
+
+/// Reader returned by `RkReader::to_target`.
+pub struct ResamplingReader<R: Read> {
+    inner: ConvertingReader<R>,
+    /// Scratch for reading from `inner`, kept across reads, and
+    /// how much of it is filled.
+    chunk: Vec<u8>,
+    filled: usize,
+    hdr: Hdr,
+    from: u64,
+    to: u64,
+    /// Input frames from `base` on, as floats.
+    frames: Vec<f32>,
+    base: u64,
+    /// Index of the next output frame.
+    next: u64,
+    eof: bool,
+    out: Vec<u8>,
+    pos: usize
+}
+
+impl<R: Read> ResamplingReader<R> {
+    /// Header of the stream as it's being read, with the target
+    /// rate and format, and the frame count scaled to match.
+    pub fn header(&self) -> Hdr {
+        self.hdr
+    }
+
+    /// Interpolate every output frame the input read so far has
+    /// both neighbours of, or all that are left at the EOF.
+    fn resample(&mut self) {
+        let ch = self.hdr.channels as usize;
+        let avail = self.base + (self.frames.len() / ch) as u64;
+        let end = (avail as u128 * self.to as u128 / self.from as u128) as u64;
+        let mut s = Vec::new();
+
+        loop {
+            let at = self.next as u128 * self.from as u128;
+            let a = (at / self.to as u128) as u64;
+            if self.eof && self.next >= end || !self.eof && a + 1 >= avail
+            { break }
+
+            let b = (a + 1).min(avail - 1);
+            let t = (at % self.to as u128) as f32 / self.to as f32;
+            let (a, b) = ((a - self.base) as usize * ch,
+                          (b - self.base) as usize * ch);
+
+            for c in 0..ch {
+                let (x, y) = (self.frames[a + c], self.frames[b + c]);
+                s.push(x + (y - x) * t);
+            }
+            self.next += 1;
+        }
+
+        let keep = ((self.next as u128 * self.from as u128 / self.to as u128) as u64)
+            .clamp(self.base, avail);
+        self.frames.drain(..(keep - self.base) as usize * ch);
+        self.base = keep;
+
+        self.out.clear();
+        self.pos = 0;
+        encode_f32(&s, self.hdr.format, &mut self.out);
+    }
+}
+
+impl<R: Read> Read for ResamplingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.out.len() {
+            if self.eof { return Ok(0) }
+
+            // a read that fails keeps what it got for the next one.
+            fill_at(&mut self.inner, &mut self.chunk, &mut self.filled)?;
+            let n = std::mem::take(&mut self.filled);
+            self.eof = n < self.chunk.len();
+
+            self.frames.extend(self.chunk[..n].chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])));
+            self.resample();
+        }
+
+        let n = buf.len().min(self.out.len() - self.pos);
+        buf[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+#[test]
+fn resample_48k_to_44k1() {
+    use crate::utils::testutil::assert_samples_close;
+
+    let src: Vec<f32> = (0..48000 * 2).map(|i| (i / 2) as f32 / 48000.0).collect();
+    let out = resample_to_supported(&src, 48000, 44100, 2);
+    assert!(out.len() == 44100 * 2);
+
+    // a ramp should stay a ramp.
+    let ramp: Vec<f32> = (0..44100 * 2).map(|i| (i / 2) as f32 / 44100.0).collect();
+    assert_samples_close(&out, &ramp, 1e-3);
+
+    assert!(resample_to_supported(&src[..3], 48000, 44100, 0) == src[..3]);
+    assert!(resample_to_supported(&src[..3], 0, 44100, 1) == src[..3]);
+
+    let h = Hdr { format: Fmt::Int16, rate: 48000, channels: 2,
+                  frames: Some(0) };
+    let mut file = Vec::new();
+    let oh = mux_resampled(&mut file, h, &src, None).unwrap();
+    assert!(oh.rate == 44100 && oh.frames == Some(44100));
+    assert!(file.len() == 12 + 44100 * 4);
+}
+
+#[test]
+fn read_44k1_int16_as_48k_float() {
+    use crate::{demux, encode_to_vec};
+    use crate::utils::testutil::{assert_samples_close, f32s, generate_tone};
+
+    let h = Hdr { format: Fmt::Int16, rate: 44100, channels: 2,
+                  frames: Some(44100) };
+    let pcm = generate_tone(440.0, &h, 44100);
+    let file = encode_to_vec(h, &pcm, None).unwrap();
+
+    let mut r = demux(&file[..]).unwrap().to_target(48000, Fmt::Float32).unwrap();
+    assert!(r.header().frames == Some(48000));
+
+    let mut out = Vec::new();
+    r.read_to_end(&mut out).unwrap();
+    let out = f32s(&out);
+    assert!(out.len() == 48000 * 2);
+
+    // the same as resampling all of it at once, chunks and all.
+    let mut whole = Vec::new();
+    crate::convert(Fmt::Int16, Fmt::Float32, &pcm, &mut whole);
+    assert_samples_close(&out, &resample_to_supported(&f32s(&whole), 44100,
+                                                      48000, 2), 1e-4);
+}
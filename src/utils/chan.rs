@@ -0,0 +1,67 @@
+use super::RErr;
+
+/// Interleave separate channel buffers into frames, fails
+/// with `RErr::Channels` if they aren't all the same length.
+pub fn interleave<T: Copy>(planar: &[&[T]]) -> Result<Vec<T>, RErr> {
+  let len = planar.first().map_or(0, |c| c.len());
+  if planar.iter().any(|c| c.len() != len) { return Err(RErr::Channels) }
+
+  let mut out = Vec::with_capacity(len * planar.len());
+  for i in 0..len {
+    out.extend(planar.iter().map(|c| c[i]))
+  }
+
+  Ok(out)
+}
+
+/// Pick out the samples of channel `index` from interleaved
+/// frames of `channels` samples, ignoring a trailing partial
+/// frame. Fails with `RErr::Channels` unless `index < channels`.
+pub fn extract_channel<T: Copy>(interleaved: &[T], channels: usize, index: usize)
+  -> Result<Vec<T>, RErr> {
+  if index >= channels { return Err(RErr::Channels) }
+
+  Ok(interleaved.chunks_exact(channels).map(|f| f[index]).collect())
+}
+
+/// Scale the samples of every channel of interleaved frames by
+/// its gain in `gains`, a trailing partial frame included. Going
+/// past full scale is fine, conversion to an integer format
+/// clips it. Fails with `RErr::Channels` unless there's a gain
+/// for each of the `channels`, and at least one.
+pub fn apply_gain(samples: &mut [f32], channels: usize, gains: &[f32])
+  -> Result<(), RErr> {
+  if channels == 0 || gains.len() != channels { return Err(RErr::Channels) }
+
+  for f in samples.chunks_mut(channels) {
+    f.iter_mut().zip(gains).for_each(|(s, g)| *s *= g)
+  }
+
+  Ok(())
+}
+
+#[test]
+fn interleave_channels() {
+  assert!(interleave(&[&[1, 2, 3][..], &[4, 5, 6]]).unwrap()
+          == [1, 4, 2, 5, 3, 6]);
+  assert!(matches!(interleave(&[&[1, 2][..], &[3]]), Err(RErr::Channels)));
+}
+
+#[test]
+fn extract_one_channel() {
+  let s = [1, 4, 2, 5, 3, 6, 7];
+  assert!(extract_channel(&s, 2, 1).unwrap() == [4, 5, 6]);
+  assert!(extract_channel(&s, 2, 0).unwrap() == [1, 2, 3]);
+  assert!(matches!(extract_channel(&s, 2, 2), Err(RErr::Channels)));
+  assert!(matches!(extract_channel(&s, 0, 0), Err(RErr::Channels)));
+}
+
+#[test]
+fn gain_per_channel() {
+  let mut s = [0.5, 0.5, -1.0, -1.0, 0.25];
+  apply_gain(&mut s, 2, &[0.5, 1.0]).unwrap();
+  assert!(s == [0.25, 0.5, -0.5, -1.0, 0.125]);
+
+  assert!(matches!(apply_gain(&mut s, 2, &[1.0]), Err(RErr::Channels)));
+  assert!(matches!(apply_gain(&mut s, 0, &[]), Err(RErr::Channels)));
+}
@@ -0,0 +1,255 @@
+use super::Fmt;
+
+/// A single decoded sample, integers keep their bit width so
+/// converting between them never goes through a float.
+#[derive(Copy, Clone)]
+enum Val {
+  Int(i64, u32),
+  Float(f64)
+}
+
+fn get(f: Fmt, b: &[u8]) -> Val {
+  let mut a = [0u8; 8];
+  a[..b.len()].copy_from_slice(b);
+
+  match f {
+    Fmt::Int8    => Val::Int(b[0] as i8 as i64, 8),
+    Fmt::Int16   => Val::Int(i16::from_le_bytes([a[0], a[1]]) as i64, 16),
+    Fmt::Int32   => Val::Int(i32::from_le_bytes([a[0], a[1], a[2], a[3]])
+                               as i64, 32),
+    Fmt::Int64   => Val::Int(i64::from_le_bytes(a), 64),
+    Fmt::Float32 => Val::Float(f32::from_le_bytes([a[0], a[1], a[2], a[3]])
+                               as f64),
+    Fmt::Float64 => Val::Float(f64::from_le_bytes(a))
+  }
+}
+
+/// Scale a float to a `bits` wide integer, saturating it and
+/// telling if it had to. Infinities saturate like any other
+/// out of range value, NaN comes out as silence and counts as
+/// clipped.
+fn quantize(x: f64, bits: u32) -> (i64, bool) {
+  if x.is_nan() { return (0, true) }
+
+  let max = ((1u64 << (bits - 1)) - 1) as f64;
+  let y = (x * (max + 1.0)).round();
+
+  (y.clamp(-max - 1.0, max) as i64, !(-max - 1.0..=max).contains(&y))
+}
+
+fn put(f: Fmt, v: Val, out: &mut Vec<u8>) {
+  let bits = (f.size() * 8) as u32;
+  let int = |v: Val| match v {
+    Val::Int(x, b) if b >= bits => x >> (b - bits),
+    Val::Int(x, b) => x << (bits - b),
+    Val::Float(x) => quantize(x, bits).0
+  };
+  let float = |v: Val| match v {
+    Val::Int(x, b) => x as f64 / (1u64 << (b - 1)) as f64,
+    Val::Float(x) => x
+  };
+
+  match f {
+    Fmt::Int8    => out.push(int(v) as i8 as u8),
+    Fmt::Int16   => out.extend_from_slice(&(int(v) as i16).to_le_bytes()),
+    Fmt::Int32   => out.extend_from_slice(&(int(v) as i32).to_le_bytes()),
+    Fmt::Int64   => out.extend_from_slice(&int(v).to_le_bytes()),
+    Fmt::Float32 => out.extend_from_slice(&(float(v) as f32).to_le_bytes()),
+    Fmt::Float64 => out.extend_from_slice(&float(v).to_le_bytes())
+  }
+}
+
+/// Convert the samples in `src` coded as `from` to `to`, and
+/// append them to `out`. Integers map to floats in [-1, 1),
+/// floats outside of that range saturate when going back to
+/// integers, infinities included, and NaN becomes 0. A partial
+/// sample at the end of `src` is ignored.
+pub fn convert(from: Fmt, to: Fmt, src: &[u8], out: &mut Vec<u8>) {
+  out.reserve(src.len() / from.size() * to.size());
+  for b in src.chunks_exact(from.size()) {
+    put(to, get(from, b), out)
+  }
+}
+
+/// Noise added ahead of quantizing to a lower bit depth, which
+/// trades the distortion of plain rounding for a noise floor.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum DitherMode {
+  /// Just round, keeping conversions bit-exact.
+  #[default]
+  None,
+  /// Triangular (TPDF) noise of up to one LSB of the target
+  /// either way.
+  Triangular
+}
+
+/// Xorshift noise source, seeded the same every time so dithered
+/// output is reproducible.
+struct Noise(u64);
+
+impl Noise {
+  /// Uniform in [-0.5, 0.5).
+  fn uniform(&mut self) -> f64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    (self.0 >> 11) as f64 / (1u64 << 53) as f64 - 0.5
+  }
+
+  fn triangular(&mut self) -> f64 {
+    self.uniform() + self.uniform()
+  }
+}
+
+/// Same as `convert`, dithering with `mode` when going to an
+/// integer format of fewer bits than the source has. Anything
+/// else converts exactly as `convert` does.
+pub fn convert_dithered(from: Fmt, to: Fmt, src: &[u8], out: &mut Vec<u8>,
+                        mode: DitherMode) {
+  let float = |f| matches!(f, Fmt::Float32 | Fmt::Float64);
+  let bits = (to.size() * 8) as u32;
+
+  if mode == DitherMode::None || float(to)
+     || !float(from) && from.size() <= to.size() {
+    return convert(from, to, src, out)
+  }
+
+  let lsb = 1.0 / (1u64 << (bits - 1)) as f64;
+  let mut noise = Noise(0x9e37_79b9_7f4a_7c15);
+
+  out.reserve(src.len() / from.size() * to.size());
+  for b in src.chunks_exact(from.size()) {
+    let x = match get(from, b) {
+      Val::Int(x, b) => x as f64 / (1u64 << (b - 1)) as f64,
+      Val::Float(x) => x
+    };
+
+    put(to, Val::Float(x + noise.triangular() * lsb), out)
+  }
+}
+
+/// Code float samples as `to` and append them to `out`.
+pub(crate) fn encode_f32(s: &[f32], to: Fmt, out: &mut Vec<u8>) {
+  out.reserve(s.len() * to.size());
+  for &x in s { put(to, Val::Float(x as f64), out) }
+}
+
+/// Code double precision float samples as `to` and append
+/// them to `out`.
+#[cfg(test)]
+pub(crate) fn encode_f64(s: &[f64], to: Fmt, out: &mut Vec<u8>) {
+  out.reserve(s.len() * to.size());
+  for &x in s { put(to, Val::Float(x), out) }
+}
+
+macro_rules! from_f32 {
+  ($($t:ty, $sat:ident, $checked:ident;)*) => { $(
+    /// Convert floats in [-1, 1) to integers, saturating
+    /// anything outside of that range. `+inf` and `-inf` go to
+    /// the largest and smallest integer, NaN to 0.
+    pub fn $sat(s: &[f32]) -> Vec<$t> {
+      $checked(s).0
+    }
+
+    /// Same as the saturating conversion, but also counts
+    /// how many samples were clipped in the process.
+    pub fn $checked(s: &[f32]) -> (Vec<$t>, usize) {
+      let mut clipped = 0;
+      let out = s.iter().map(|&x| {
+        let (y, c) = quantize(x as f64, <$t>::BITS);
+        clipped += c as usize;
+        y as $t
+      }).collect();
+
+      (out, clipped)
+    }
+  )* }
+}
+
+from_f32!(i8,  f32_to_i8,  f32_to_i8_checked;
+          i16, f32_to_i16, f32_to_i16_checked;
+          i32, f32_to_i32, f32_to_i32_checked;);
+
+#[test]
+fn f32_to_i16_clipping() {
+  let (s, clipped) = f32_to_i16_checked(&[0.0, 0.5, -1.0, 1.0, -1.5, 2.0]);
+  assert!(s == [0, 16384, i16::MIN, i16::MAX, i16::MIN, i16::MAX]);
+  assert!(clipped == 3);
+  assert!(f32_to_i16(&[2.0]) == [i16::MAX]);
+}
+
+#[test]
+fn non_finite_floats_to_ints() {
+  let s = [f32::NAN, f32::INFINITY, f32::NEG_INFINITY, -f32::NAN];
+  let (out, clipped) = f32_to_i32_checked(&s);
+  assert!(out == [0, i32::MAX, i32::MIN, 0] && clipped == 4);
+  assert!(f32_to_i8(&s) == [0, i8::MAX, i8::MIN, 0]);
+
+  let mut src = Vec::new();
+  encode_f32(&s, Fmt::Float64, &mut src);
+  let mut out = Vec::new();
+  convert(Fmt::Float64, Fmt::Int64, &src, &mut out);
+  let ints: Vec<i64> = out.chunks_exact(8)
+    .map(|c| i64::from_le_bytes([c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7]]))
+    .collect();
+  assert!(ints == [0, i64::MAX, i64::MIN, 0]);
+}
+
+#[test]
+fn convert_between_formats() {
+  let mut out = Vec::new();
+  convert(Fmt::Int16, Fmt::Int8, &[0x00, 0x80, 0xff, 0x7f], &mut out);
+  assert!(out == [0x80, 0x7f]);
+
+  out.clear();
+  convert(Fmt::Int8, Fmt::Float32, &[0x80, 0x40], &mut out);
+  assert!(out[..4] == (-1.0f32).to_le_bytes());
+  assert!(out[4..] == 0.5f32.to_le_bytes());
+
+  out.clear();
+  convert(Fmt::Float64, Fmt::Int16, &2.0f64.to_le_bytes(), &mut out);
+  assert!(out == i16::MAX.to_le_bytes());
+}
+
+#[test]
+fn float_int16_roundtrip() {
+  use super::testutil::{assert_samples_close, f32s};
+
+  let src: Vec<f32> = (0..200).map(|i| (i as f32 * 0.1).sin() * 0.9).collect();
+  let mut pcm = Vec::new();
+  encode_f32(&src, Fmt::Int16, &mut pcm);
+
+  let mut out = Vec::new();
+  convert(Fmt::Int16, Fmt::Float32, &pcm, &mut out);
+  assert_samples_close(&f32s(&out), &src, 1.0 / 32768.0);
+}
+
+#[test]
+fn triangular_dither() {
+  use super::testutil::{assert_samples_close, f32s};
+
+  let src: Vec<f32> = (0..1000).map(|i| (i as f32 * 0.01).sin() * 0.3).collect();
+  let mut pcm = Vec::new();
+  encode_f32(&src, Fmt::Float32, &mut pcm);
+
+  let (mut plain, mut dithered) = (Vec::new(), Vec::new());
+  convert(Fmt::Float32, Fmt::Int16, &pcm, &mut plain);
+  convert_dithered(Fmt::Float32, Fmt::Int16, &pcm, &mut dithered,
+                   DitherMode::Triangular);
+  assert!(plain != dithered);
+
+  let back = |b: &[u8]| {
+    let mut f = Vec::new();
+    convert(Fmt::Int16, Fmt::Float32, b, &mut f);
+    f32s(&f)
+  };
+  assert_samples_close(&back(&dithered), &back(&plain), 1.0 / 32768.0);
+
+  // widening, or going to floats, has nothing to dither.
+  let mut out = Vec::new();
+  convert_dithered(Fmt::Int16, Fmt::Int32, &plain, &mut out,
+                   DitherMode::Triangular);
+  let mut exact = Vec::new();
+  convert(Fmt::Int16, Fmt::Int32, &plain, &mut exact);
+  assert!(out == exact);
+}
@@ -0,0 +1,63 @@
+use std::io::{self, Write};
+
+/// Writer passing everything through to `W`, calling `f` with
+/// the total number of bytes written so far after every write.
+///
+/// Wrap the sink given to `mux` in it to drive a progress
+/// indicator, for a compressed stream the count is in bytes of
+/// output, not of PCM data. Writers that don't need progress
+/// just aren't wrapped, so there's nothing to pay for then.
+pub struct CountingWriter<W: Write, F: FnMut(u64)> {
+  inner: W,
+  count: u64,
+  f: F
+}
+
+impl<W: Write, F: FnMut(u64)> CountingWriter<W, F> {
+  pub fn new(inner: W, f: F) -> Self {
+    CountingWriter { inner, count: 0, f }
+  }
+
+  /// Bytes written through so far.
+  pub fn count(&self) -> u64 {
+    self.count
+  }
+
+  pub fn into_inner(self) -> W {
+    self.inner
+  }
+}
+
+impl<W: Write, F: FnMut(u64)> Write for CountingWriter<W, F> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let n = self.inner.write(buf)?;
+    if n > 0 {
+      self.count += n as u64;
+      (self.f)(self.count);
+    }
+
+    Ok(n)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.inner.flush()
+  }
+}
+
+#[test]
+fn counting_writer_total() {
+  use crate::{mux, Fmt, Hdr};
+
+  let h = Hdr { format: Fmt::Int8, rate: 8000, channels: 1,
+                ..Default::default() };
+  let mut seen = 0;
+
+  let w = CountingWriter::new(Vec::new(), |n| seen = n);
+  let mut rkout = mux(w, h, None).unwrap();
+  rkout.write_all(&[7; 1000]).unwrap();
+  let w = rkout.finish().unwrap();
+
+  assert!(w.count() == 1002);
+  drop(w);
+  assert!(seen == 1002);
+}
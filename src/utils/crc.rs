@@ -0,0 +1,100 @@
+//! CRC-32 (the IEEE one of zlib and PNG) over the PCM data, and
+//! the reader side checking it against the four bytes trailing
+//! the PCM data of the payload.
+use std::io;
+
+use super::RErr;
+
+const TABLE: [u32; 256] = {
+  let mut t = [0u32; 256];
+  let mut i = 0;
+  while i < 256 {
+    let mut c = i as u32;
+    let mut k = 0;
+    while k < 8 {
+      c = if c & 1 != 0 { 0xedb8_8320 ^ c >> 1 } else { c >> 1 };
+      k += 1;
+    }
+    t[i] = c;
+    i += 1;
+  }
+  t
+};
+
+/// Running CRC-32 of the bytes it's been fed.
+#[derive(Copy, Clone)]
+pub(crate) struct Crc32(u32);
+
+impl Default for Crc32 {
+  fn default() -> Self {
+    Crc32(!0)
+  }
+}
+
+impl Crc32 {
+  pub(crate) fn update(&mut self, b: &[u8]) {
+    for &x in b {
+      self.0 = TABLE[((self.0 ^ x as u32) & 0xff) as usize] ^ self.0 >> 8;
+    }
+  }
+
+  pub(crate) fn value(&self) -> u32 {
+    !self.0
+  }
+}
+
+/// Holds back the last four bytes of what it reads, checking
+/// them against the CRC of everything before them at the EOF.
+#[derive(Default)]
+pub(crate) struct Trailer {
+  held: Vec<u8>,
+  crc: Crc32,
+  /// Started partway into the PCM data, so the CRC can't match.
+  unchecked: bool
+}
+
+impl Trailer {
+  /// Only holds back the CRC, for reading from partway in.
+  pub(crate) fn unchecked() -> Self {
+    Trailer { unchecked: true, ..Default::default() }
+  }
+
+  /// Read through `next`, failing with `RErr::Checksum` at the
+  /// EOF if the CRC doesn't match, or `RErr::Truncated` if there
+  /// aren't even the four bytes of it.
+  pub(crate) fn read<F>(&mut self, mut next: F, buf: &mut [u8])
+    -> io::Result<usize>
+    where F: FnMut(&mut [u8]) -> io::Result<usize> {
+    if buf.is_empty() { return Ok(0) }
+
+    loop {
+      let n = next(buf)?;
+      if n == 0 {
+        return match self.held[..] {
+          [a, b, c, d] if self.unchecked || u32::from_le_bytes([a, b, c, d])
+                                              == self.crc.value() => Ok(0),
+          [_, _, _, _] => Err(RErr::Checksum.into()),
+          _ => Err(RErr::Truncated.into())
+        }
+      }
+
+      self.held.extend_from_slice(&buf[..n]);
+      if self.held.len() <= 4 { continue }
+
+      let out = self.held.len() - 4;
+      buf[..out].copy_from_slice(&self.held[..out]);
+      self.held.drain(..out);
+      self.crc.update(&buf[..out]);
+
+      return Ok(out)
+    }
+  }
+}
+
+#[test]
+fn crc32_check_value() {
+  let mut c = Crc32::default();
+  c.update(b"12345");
+  c.update(b"6789");
+  assert!(c.value() == 0xcbf4_3926);
+}
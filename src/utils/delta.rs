@@ -0,0 +1,88 @@
+//! First-order prediction of integer PCM data, applied ahead of
+//! silence coding and compression. Every sample is replaced by
+//! its difference from the previous one of the same channel, as
+//! little-endian integers of the sample's width, wrapping around
+//! on overflow so that it's exact. Smooth waveforms turn into
+//! small numbers that compress better.
+//!
+//! Differences are taken byte by byte with a borrow carried
+//! through the sample, so frames split across reads or writes
+//! need no buffering.
+
+/// Differences samples as they're written, or adds them back
+/// up as they're read, keeping the last frame either way.
+pub(crate) struct Delta {
+  /// Last frame's samples, as they were before the filter.
+  prev: Vec<u8>,
+  size: usize,
+  /// Offset of the next byte within the frame.
+  at: usize,
+  /// Borrow, or carry, out of the last byte of the sample.
+  carry: bool,
+  /// Filtered copy of what's being written.
+  out: Vec<u8>
+}
+
+impl Delta {
+  pub(crate) fn new(size: usize, channels: usize) -> Self {
+    Delta { prev: vec![0; size * channels], size, at: 0, carry: false,
+            out: Vec::new() }
+  }
+
+  fn next(&mut self) -> (u8, bool) {
+    if self.at.is_multiple_of(self.size) { self.carry = false }
+    (self.prev[self.at], self.carry)
+  }
+
+  fn advance(&mut self, cur: u8, carry: bool) {
+    self.prev[self.at] = cur;
+    self.carry = carry;
+    self.at = (self.at + 1) % self.prev.len();
+  }
+
+  /// Differences of the samples in `buf`, valid until the next call.
+  pub(crate) fn encode(&mut self, buf: &[u8]) -> &[u8] {
+    let mut out = std::mem::take(&mut self.out);
+    out.clear();
+
+    for &cur in buf {
+      let (p, c) = self.next();
+      let (d, o1) = cur.overflowing_sub(p);
+      let (d, o2) = d.overflowing_sub(c as u8);
+      out.push(d);
+      self.advance(cur, o1 | o2);
+    }
+
+    self.out = out;
+    &self.out
+  }
+
+  /// Add the differences in `buf` back up to samples, in place.
+  pub(crate) fn decode(&mut self, buf: &mut [u8]) {
+    for b in buf {
+      let (p, c) = self.next();
+      let (s, o1) = b.overflowing_add(p);
+      let (s, o2) = s.overflowing_add(c as u8);
+      *b = s;
+      self.advance(s, o1 | o2);
+    }
+  }
+}
+
+#[test]
+fn delta_roundtrip() {
+  let s: Vec<i16> = vec![1000, -5, 1001, -32768, 1003, 32767, 0, 0];
+  let pcm: Vec<u8> = s.iter().flat_map(|x| x.to_le_bytes().to_vec()).collect();
+
+  let mut d = Delta::new(2, 2);
+  let mut coded = Vec::new();
+  for c in pcm.chunks(3) { coded.extend_from_slice(d.encode(c)) }
+
+  let diffs: Vec<i16> = coded.chunks(2)
+    .map(|b| i16::from_le_bytes([b[0], b[1]])).collect();
+  assert!(diffs == [1000, -5, 1, -32763, 2, -1, -1003, -32767]);
+
+  let mut d = Delta::new(2, 2);
+  for c in coded.chunks_mut(5) { d.decode(c) }
+  assert!(coded == pcm);
+}
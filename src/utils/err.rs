@@ -1,3 +1,7 @@
+use core::fmt;
+#[cfg(any(test, not(feature = "no_std")))]
+use std::io;
+
 #[derive(Debug)]
 pub enum RErr {
   /// Invalid start-code for identification
@@ -12,5 +16,130 @@ pub enum RErr {
   /// Input samplerate was not valid for RKPI2.
   Rate,
   /// Number of input channels was not valid.
-  Channels
-}
\ No newline at end of file
+  Channels,
+  /// Payload didn't match the compression flag
+  /// of the header, or failed to decompress.
+  Decompress,
+  /// Compression was asked for, or the payload is
+  /// compressed, but the `compression` feature is off.
+  CompressionUnsupported,
+  /// Input ended before everything it was
+  /// supposed to contain was there.
+  Truncated,
+  /// Header has flags set that this version doesn't
+  /// know, or has reserved bits set or is in a
+  /// non-canonical form, those rejected only when
+  /// demuxing with `Strictness::Strict`.
+  Inconsistent,
+  /// A range of frames was backwards, or pointed
+  /// to frames that can't be reached anymore, or a
+  /// buffer is too small for a frame.
+  Range,
+  /// Header is of a later revision of the format
+  /// than this version can read.
+  Version,
+  /// Frame count backfill was asked for over a
+  /// writer that can't seek back to the header.
+  Unseekable,
+  /// Payload is coded with a codec this version
+  /// doesn't know, or that can't be used there.
+  Codec,
+  /// Payload ended partway through a frame, caught
+  /// only when demuxing with `Strictness::Strict`.
+  Misaligned,
+  /// PCM data didn't match the CRC stored after it
+  /// (see `MuxOptions::pcm_crc`).
+  Checksum,
+  /// Tag block is malformed, or tags were given
+  /// where they can't be stored.
+  Tags,
+  /// Payload decodes to more PCM data than allowed
+  /// by `DemuxOptions::max_decompressed_bytes`.
+  LimitExceeded,
+  /// Streams being joined differ in sampleformat,
+  /// samplerate or number of channels.
+  Mismatch,
+  /// A non-blocking source had nothing to read yet,
+  /// the read can be retried once it does.
+  WouldBlock
+}
+impl fmt::Display for RErr {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {
+      RErr::StartCode => "not an RKPI2 stream, bad start code",
+      RErr::Format => "reserved or mismatched sampleformat",
+      RErr::IO => "I/O error",
+      RErr::Rate => "unsupported samplerate",
+      RErr::Channels => "unsupported number of channels",
+      RErr::Decompress => "payload failed to decompress",
+      RErr::CompressionUnsupported => "built without compression support",
+      RErr::Truncated => "input ended early",
+      RErr::Inconsistent => "inconsistent or non-canonical header",
+      RErr::Range => "frame range out of reach",
+      RErr::Version => "unsupported format revision",
+      RErr::Unseekable => "frame count backfill needs a seekable writer",
+      RErr::Codec => "unsupported codec",
+      RErr::Misaligned => "payload ends partway through a frame",
+      RErr::Checksum => "PCM data doesn't match its CRC",
+      RErr::Tags => "malformed or misplaced tags",
+      RErr::LimitExceeded => "payload decodes past the size limit",
+      RErr::Mismatch => "streams differ in format, rate or channels",
+      RErr::WouldBlock => "source isn't ready, try again"
+    })
+  }
+}
+
+#[cfg(any(test, not(feature = "no_std")))]
+impl std::error::Error for RErr {}
+
+/// An `RErr` converted to an `io::Error` comes back as it was,
+/// otherwise only the kind of error survives, as `RErr::IO`
+/// doesn't carry the original one.
+#[cfg(any(test, not(feature = "no_std")))]
+impl From<io::Error> for RErr {
+  fn from(e: io::Error) -> Self {
+    let kind = e.kind();
+    if let Some(Ok(r)) = e.into_inner().map(|e| e.downcast::<RErr>()) {
+      return *r
+    }
+
+    match kind {
+      io::ErrorKind::UnexpectedEof => RErr::Truncated,
+      io::ErrorKind::WouldBlock => RErr::WouldBlock,
+      _ => RErr::IO
+    }
+  }
+}
+
+#[cfg(any(test, not(feature = "no_std")))]
+impl From<RErr> for io::Error {
+  fn from(e: RErr) -> Self {
+    let kind = match e {
+      RErr::IO => io::ErrorKind::Other,
+      RErr::Truncated => io::ErrorKind::UnexpectedEof,
+      RErr::CompressionUnsupported => io::ErrorKind::Unsupported,
+      RErr::Unseekable => io::ErrorKind::Unsupported,
+      RErr::WouldBlock => io::ErrorKind::WouldBlock,
+      _ => io::ErrorKind::InvalidData
+    };
+
+    io::Error::new(kind, e)
+  }
+}
+
+#[test]
+fn io_error_kinds() {
+  let e = io::Error::from(RErr::Truncated);
+  assert!(e.kind() == io::ErrorKind::UnexpectedEof);
+  assert!(e.to_string() == "input ended early");
+
+  assert!(io::Error::from(RErr::StartCode).kind()
+          == io::ErrorKind::InvalidData);
+  assert!(matches!(RErr::from(io::Error::from(RErr::Truncated)),
+                   RErr::Truncated));
+  assert!(matches!(RErr::from(io::Error::other("x")), RErr::IO));
+  assert!(matches!(RErr::from(io::Error::from(io::ErrorKind::WouldBlock)),
+                   RErr::WouldBlock));
+  assert!(matches!(RErr::from(io::Error::from(RErr::Misaligned)),
+                   RErr::Misaligned));
+}
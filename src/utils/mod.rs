@@ -1,8 +1,29 @@
-use std::convert::TryFrom;
+use core::convert::TryFrom;
+#[cfg(any(test, not(feature = "no_std")))]
+pub mod chan;
+#[cfg(any(test, not(feature = "no_std")))]
+pub mod conv;
+#[cfg(any(test, not(feature = "no_std")))]
+pub mod count;
+#[cfg(any(test, not(feature = "no_std")))]
+pub(crate) mod crc;
+#[cfg(any(test, not(feature = "no_std")))]
+pub(crate) mod delta;
 pub mod err;
+pub mod opts;
+#[cfg(any(test, not(feature = "no_std")))]
+pub(crate) mod rle;
+#[cfg(any(test, not(feature = "no_std")))]
+pub mod ring;
+#[cfg(any(test, not(feature = "no_std")))]
+pub mod sample;
+#[cfg(test)]
+pub(crate) mod testutil;
 pub use err::RErr;
+pub use opts::{DemuxOptions, MuxOptions, Strictness, UnknownFormat};
 
-/// Sampleformat to code PCM audio data with.
+/// Sampleformat to code PCM audio data with, multi-byte
+/// samples are always stored in little-endian order.
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Fmt {
   Int8    = 0, Int16   = 1,
@@ -10,14 +31,146 @@ pub enum Fmt {
   Float32 = 4, Float64 = 5
 }
 
+impl Fmt {
+  /// Every sampleformat, in the order of their codes.
+  pub fn all() -> &'static [Fmt] {
+    &[Fmt::Int8, Fmt::Int16, Fmt::Int32, Fmt::Int64,
+      Fmt::Float32, Fmt::Float64]
+  }
+
+  /// Sampleformat with the code `f`, if there's one.
+  pub(crate) const fn from_code(f: u8) -> Option<Fmt> {
+    match f {
+      0 => Some(Fmt::Int8), 1 => Some(Fmt::Int16),
+      2 => Some(Fmt::Int32), 3 => Some(Fmt::Int64),
+      4 => Some(Fmt::Float32), 5 => Some(Fmt::Float64),
+      _ => None
+    }
+  }
+
+  /// Size of a single sample in bytes.
+  pub const fn size(self) -> usize {
+    match self {
+      Fmt::Int8 => 1, Fmt::Int16 => 2,
+      Fmt::Int32 | Fmt::Float32 => 4,
+      Fmt::Int64 | Fmt::Float64 => 8
+    }
+  }
+
+  /// Name of the Rust type implementing `Sample` for the
+  /// format, such as `"i16"` for `Int16`.
+  pub const fn sample_type_name(self) -> &'static str {
+    match self {
+      Fmt::Int8 => "i8", Fmt::Int16 => "i16",
+      Fmt::Int32 => "i32", Fmt::Int64 => "i64",
+      Fmt::Float32 => "f32", Fmt::Float64 => "f64"
+    }
+  }
+
+  /// Whether samples are IEEE floats rather than signed integers.
+  pub const fn is_float(self) -> bool {
+    matches!(self, Fmt::Float32 | Fmt::Float64)
+  }
+
+  /// Width of a sample in bits, the reverse of `from_parts`
+  /// along with `is_float`.
+  pub const fn bits(self) -> u32 {
+    self.size() as u32 * 8
+  }
+
+  /// Sampleformat of signed integers or floats `bits` wide, if
+  /// there's one; 24-bit or unsigned integers, for one, get
+  /// `None`.
+  pub const fn from_parts(bits: u32, float: bool) -> Option<Fmt> {
+    match (bits, float) {
+      (8, false) => Some(Fmt::Int8), (16, false) => Some(Fmt::Int16),
+      (32, false) => Some(Fmt::Int32), (64, false) => Some(Fmt::Int64),
+      (32, true) => Some(Fmt::Float32), (64, true) => Some(Fmt::Float64),
+      _ => None
+    }
+  }
+}
+
+/// Mapping to and from cpal's sample formats, for feeding
+/// devices or recording from them.
+#[cfg(feature = "cpal")]
+impl Fmt {
+  /// cpal's counterpart of the sampleformat.
+  pub fn to_cpal(self) -> Option<cpal::SampleFormat> {
+    use cpal::SampleFormat as S;
+    Some(match self {
+      Fmt::Int8 => S::I8, Fmt::Int16 => S::I16,
+      Fmt::Int32 => S::I32, Fmt::Int64 => S::I64,
+      Fmt::Float32 => S::F32, Fmt::Float64 => S::F64
+    })
+  }
+
+  /// Sampleformat of a cpal one, `None` for those that have no
+  /// counterpart: `I24` and the unsigned ones.
+  pub fn from_cpal(f: cpal::SampleFormat) -> Option<Fmt> {
+    use cpal::SampleFormat as S;
+    match f {
+      S::I8 => Some(Fmt::Int8), S::I16 => Some(Fmt::Int16),
+      S::I32 => Some(Fmt::Int32), S::I64 => Some(Fmt::Int64),
+      S::F32 => Some(Fmt::Float32), S::F64 => Some(Fmt::Float64),
+      _ => None
+    }
+  }
+}
+
+/// Mapping to and from symphonia's sample formats, for the
+/// audio it decodes.
+#[cfg(feature = "symphonia")]
+impl Fmt {
+  /// symphonia's counterpart of the sampleformat, `None` for
+  /// `Int64`, as it has no 64-bit integers.
+  pub fn to_symphonia(self) -> Option<symphonia::core::sample::SampleFormat> {
+    use symphonia::core::sample::SampleFormat as S;
+    match self {
+      Fmt::Int8 => Some(S::S8), Fmt::Int16 => Some(S::S16),
+      Fmt::Int32 => Some(S::S32), Fmt::Int64 => None,
+      Fmt::Float32 => Some(S::F32), Fmt::Float64 => Some(S::F64)
+    }
+  }
+
+  /// Sampleformat of a symphonia one, `None` for those that have
+  /// no counterpart: `S24` and the unsigned ones.
+  pub fn from_symphonia(f: symphonia::core::sample::SampleFormat)
+    -> Option<Fmt> {
+    use symphonia::core::sample::SampleFormat as S;
+    match f {
+      S::S8 => Some(Fmt::Int8), S::S16 => Some(Fmt::Int16),
+      S::S32 => Some(Fmt::Int32),
+      S::F32 => Some(Fmt::Float32), S::F64 => Some(Fmt::Float64),
+      S::S24 | S::U8 | S::U16 | S::U24 | S::U32 => None
+    }
+  }
+}
+
 impl TryFrom<u8> for Fmt {
   type Error = RErr;
   fn try_from(f: u8) -> Result<Self, Self::Error> {
-    match f {
-      0 => Ok(Fmt::Int8), 1 => Ok(Fmt::Int16),
-      2 => Ok(Fmt::Int32), 3 => Ok(Fmt::Int64),
-      4 => Ok(Fmt::Float32), 5 => Ok(Fmt::Float64),
-      _ => Err(err::RErr::Format)
+    Fmt::from_code(f).ok_or(err::RErr::Format)
+  }
+}
+
+/// How the payload after the header is coded. Zstd is the
+/// only compressing codec so far, codes from 2 on are reserved
+/// for others.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Codec {
+  /// Plain PCM data.
+  #[default]
+  None = 0,
+  Zstd = 1
+}
+
+impl TryFrom<u8> for Codec {
+  type Error = RErr;
+  fn try_from(c: u8) -> Result<Self, Self::Error> {
+    match c {
+      0 => Ok(Codec::None), 1 => Ok(Codec::Zstd),
+      _ => Err(err::RErr::Codec)
     }
   }
 }
@@ -44,5 +197,181 @@ pub struct Hdr {
 
   /// Number of audio channels. The layout of channels is
   /// always interleaved.
-  pub channels: u8
-}
\ No newline at end of file
+  pub channels: u8,
+
+  /// Number of frames in the payload, if known. Stored in
+  /// the extended header, so setting it costs 10 bytes.
+  pub frames: Option<u64>
+}
+
+impl Hdr {
+  /// Size of a frame in bytes: one sample for every channel.
+  pub fn block_align(&self) -> usize {
+    self.format.size() * self.channels as usize
+  }
+
+  /// Number of whole frames in `pcm_bytes` of payload, the
+  /// bytes of a trailing partial frame are not counted. With no
+  /// channels no frame fits in, so it's 0.
+  pub fn frames_in(&self, pcm_bytes: u64) -> u64 {
+    pcm_bytes.checked_div(self.block_align() as u64).unwrap_or(0)
+  }
+
+  /// Whether both headers describe the same kind of audio:
+  /// format, rate and channels, ignoring the frame count. That
+  /// is what decides if two payloads can be joined together.
+  pub fn audio_eq(&self, other: &Hdr) -> bool {
+    self.format == other.format && self.rate == other.rate
+      && self.channels == other.channels
+  }
+
+  /// Whether a payload with the `other` header can follow one
+  /// with this header in a single stream, as `RkStreamReader`
+  /// checks. For now that's the same as `audio_eq`.
+  pub fn is_compatible_for_concat(&self, other: &Hdr) -> bool {
+    self.audio_eq(other)
+  }
+
+  /// Index of the samplerate in the table the header stores it
+  /// as, failing with `RErr::Rate` if it isn't one of them.
+  pub fn sample_rate_index(&self) -> Result<u8, RErr> {
+    crate::header::SAMPLERATES.iter().position(|&s| s == self.rate)
+      .map(|i| i as u8).ok_or(RErr::Rate)
+  }
+
+  /// Same header at another samplerate, failing with `RErr::Rate`
+  /// unless it's one of the supported ones.
+  pub fn with_rate(self, rate: u32) -> Result<Hdr, RErr> {
+    if !crate::header::SAMPLERATES.contains(&rate) { return Err(RErr::Rate) }
+    Ok(Hdr { rate, ..self })
+  }
+
+  /// Same header with another number of channels, failing with
+  /// `RErr::Channels` unless there are 1 to 8 of them.
+  pub fn with_channels(self, channels: u8) -> Result<Hdr, RErr> {
+    if !(1..=8).contains(&channels) { return Err(RErr::Channels) }
+    Ok(Hdr { channels, ..self })
+  }
+
+  /// Same header with another sampleformat. Every `Fmt` can be
+  /// muxed, this only fails to keep in line with the others.
+  pub fn with_format(self, format: Fmt) -> Result<Hdr, RErr> {
+    Ok(Hdr { format, ..self })
+  }
+}
+
+/// So stages taking `impl AsRef<Hdr>` accept a bare header
+/// as well as the readers and writers carrying one.
+impl AsRef<Hdr> for Hdr {
+  fn as_ref(&self) -> &Hdr {
+    self
+  }
+}
+
+/// Defaults to CD-like audio: 16-bit samples at 44100Hz in
+/// stereo, with no frame count.
+impl Default for Hdr {
+  fn default() -> Self {
+    Hdr { format: Fmt::Int16,
+          rate: 44100,
+          channels: 2,
+          frames: None }
+  }
+}
+
+#[test]
+fn codec_codes() {
+  assert!(Codec::try_from(Codec::Zstd as u8).unwrap() == Codec::Zstd);
+  assert!(matches!(Codec::try_from(2), Err(RErr::Codec)));
+}
+
+#[test]
+fn fmt_code_roundtrip() {
+  for &f in Fmt::all() {
+    assert!(Fmt::try_from(f as u8).unwrap() == f);
+  }
+
+  assert!(Fmt::all().len() == 6);
+  assert!(Fmt::try_from(Fmt::all().len() as u8).is_err());
+}
+
+#[test]
+fn hdr_frames_in() {
+  let h = |format, channels| Hdr { format, channels, ..Default::default() };
+
+  assert!(h(Fmt::Int8, 1).block_align() == 1);
+  assert!(h(Fmt::Int16, 2).block_align() == 4);
+  assert!(h(Fmt::Float64, 8).block_align() == 64);
+
+  assert!(h(Fmt::Int8, 1).frames_in(8000) == 8000);
+  assert!(h(Fmt::Int16, 2).frames_in(4003) == 1000);
+  assert!(h(Fmt::Int32, 3).frames_in(11) == 0);
+  assert!(h(Fmt::Float32, 6).frames_in(48) == 2);
+  assert!(h(Fmt::Int16, 0).frames_in(48) == 0);
+}
+
+#[test]
+fn hdr_with_setters() {
+  let h = Hdr::default();
+
+  assert!(matches!(h.with_rate(48001), Err(RErr::Rate)));
+  assert!(h.with_rate(96000).unwrap() == Hdr { rate: 96000, ..h });
+  assert!(matches!(h.with_channels(0), Err(RErr::Channels)));
+  assert!(matches!(h.with_channels(9), Err(RErr::Channels)));
+  assert!(h.with_channels(1).unwrap().channels == 1);
+  assert!(h.with_format(Fmt::Float32).unwrap().format == Fmt::Float32);
+}
+
+#[test]
+fn fmt_from_parts() {
+  for &f in Fmt::all() {
+    assert!(Fmt::from_parts(f.bits(), f.is_float()) == Some(f));
+  }
+
+  assert!(Fmt::from_parts(24, false).is_none());
+  assert!(Fmt::from_parts(16, true).is_none());
+  assert!(Fmt::Int16.bits() == 16 && Fmt::Float64.bits() == 64);
+}
+
+#[cfg(feature = "cpal")]
+#[test]
+fn fmt_cpal_mapping() {
+  use cpal::SampleFormat as S;
+
+  for &f in Fmt::all() {
+    assert!(Fmt::from_cpal(f.to_cpal().unwrap()) == Some(f));
+  }
+  assert!(Fmt::Int16.to_cpal() == Some(S::I16));
+
+  for &f in &[S::I24, S::U8, S::U16, S::U32, S::U64] {
+    assert!(Fmt::from_cpal(f).is_none());
+  }
+}
+
+#[cfg(feature = "symphonia")]
+#[test]
+fn fmt_symphonia_mapping() {
+  use symphonia::core::sample::SampleFormat as S;
+
+  for &f in Fmt::all() {
+    match f.to_symphonia() {
+      Some(s) => assert!(Fmt::from_symphonia(s) == Some(f)),
+      None => assert!(f == Fmt::Int64)
+    }
+  }
+  assert!(matches!(Fmt::Float32.to_symphonia(), Some(S::F32)));
+
+  for &f in &[S::S24, S::U8, S::U16, S::U24, S::U32] {
+    assert!(Fmt::from_symphonia(f).is_none());
+  }
+}
+
+#[test]
+fn hdr_audio_eq() {
+  let h = Hdr::default();
+  let counted = Hdr { frames: Some(10), ..h };
+
+  assert!(h.audio_eq(&counted) && h != counted);
+  assert!(!h.audio_eq(&Hdr { channels: 1, ..h }));
+  assert!(!h.audio_eq(&Hdr { format: Fmt::Int32, ..h }));
+}
@@ -0,0 +1,184 @@
+#[cfg(any(test, not(feature = "no_std")))]
+use crate::header::Coding;
+
+/// Knobs for `mux_with_options`, the defaults match `mux`
+/// without compression.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct MuxOptions {
+  /// Zstd compression level to compress the payload with,
+  /// `None` leaves it uncompressed.
+  pub level: Option<u8>,
+
+  /// Have every Zstd frame carry a checksum of its content,
+  /// which the decoder verifies, so a corrupted payload
+  /// fails to read with `RErr::Decompress`. Costs 4 bytes
+  /// per frame.
+  pub zstd_checksum: bool,
+
+  /// Write a frame count of zero and fill the real one in on
+  /// `finish`, for when it isn't known up-front. That means
+  /// seeking back over the output, so this is only supported
+  /// by `mux_seekable`, `mux_with_options` fails right away
+  /// with `RErr::Unseekable` instead.
+  pub backfill_frames: bool,
+
+  /// 6-bit start code to write instead of the standard one,
+  /// for private variants of the format that shouldn't pass
+  /// for RKPI2. Anything over `0x3f` fails with `RErr::StartCode`.
+  /// `None` writes the standard `0x3d`.
+  pub start_code: Option<u8>,
+
+  /// Write the PCM data first and the header, with the frame
+  /// count, after it on `finish`, for streaming out audio of
+  /// unknown length. Such a stream doesn't start with a header,
+  /// so only `demux_footer` reads it. `backfill_frames` has no
+  /// effect then.
+  pub footer: bool,
+
+  /// Collapse runs of silent frames (all samples exactly zero)
+  /// ahead of compression, which helps audio with long pauses,
+  /// mostly at low levels. Readers expand them back, untouched.
+  /// Marked in the header, so it always takes the extended form.
+  pub silence_rle: bool,
+
+  /// Follow the PCM data with its CRC-32, which readers check
+  /// once they reach the end of it, failing that last read with
+  /// `RErr::Checksum` on a mismatch. Unlike `zstd_checksum` this
+  /// covers the samples as they came in, whatever the codec,
+  /// so it also catches a faulty decoder. Costs 4 bytes (ahead
+  /// of compression), and the extended header.
+  pub pcm_crc: bool,
+
+  /// End a Zstd frame every this many frames of PCM data, as
+  /// `flush_frame` does, and store the `seek_index` at the end
+  /// of the payload on `finish`, so `demux_seekable` can seek
+  /// in the stream without being handed the index. It goes in
+  /// a Zstd skippable frame, which readers not looking for it
+  /// pass over. That costs 16 bytes, plus 16 for every frame,
+  /// on top of the frames making compression a little worse.
+  /// Only compressed payloads have an index, and footer mode
+  /// leaves it out, as `demux_footer` can't seek.
+  pub index_interval: Option<u64>,
+
+  /// Store every sample of an integer format as its difference
+  /// from the previous one of its channel, ahead of silence
+  /// coding and compression, which shrinks smooth waveforms.
+  /// Readers add them back up, it's lossless. Float formats are
+  /// left as they are, differences of floats aren't exact.
+  /// Every sample depends on all before it, so seeking back into
+  /// such a stream decodes it again from the start, index or not.
+  /// Marked in the header, so it always takes the extended form.
+  pub delta_filter: bool,
+
+  /// With a `level`, hold the first megabyte of the payload back
+  /// and compress it on trial before writing anything, storing
+  /// the whole payload uncompressed instead if that doesn't cut
+  /// it down by at least 5%, as for already-compressed or random
+  /// data. The header, flagged to match, goes out once that's
+  /// decided, or earlier on `flush`, `flush_frame` or `finish`,
+  /// on what there is by then. Until then the writer reports
+  /// the payload as compressed.
+  pub auto_store: bool
+}
+
+#[cfg(any(test, not(feature = "no_std")))]
+impl MuxOptions {
+  pub(crate) fn start_code(&self) -> u8 {
+    self.start_code.unwrap_or(crate::header::START_CODE)
+  }
+
+  /// How the payload of a stream with header `h` gets coded.
+  pub(crate) fn coding(&self, h: &super::Hdr) -> Coding {
+    let codec = match self.level {
+      Some(_) => super::Codec::Zstd,
+      None => super::Codec::None
+    };
+
+    Coding { codec, rle: self.silence_rle, crc: self.pcm_crc,
+             delta: self.delta_filter && !h.format.is_float() }
+  }
+}
+
+/// How picky `demux_with_options` is about its input.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Strictness {
+  /// Parse whatever can be made sense of, ignoring reserved
+  /// bits of the extended header. Flags it doesn't know are
+  /// rejected all the same, as they may add fields to it.
+  #[default]
+  Lenient,
+  /// Reject internally inconsistent or non-canonical headers
+  /// with `RErr::Inconsistent`, and check the payload against
+  /// the compression flag (see `DemuxOptions::strictness`).
+  /// A payload ending partway through a frame fails the read
+  /// that hits its end with `RErr::Misaligned`.
+  Strict
+}
+
+/// How `demux_with_options` treats a reserved format code.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum UnknownFormat {
+  /// Fail with `RErr::Format`.
+  #[default]
+  Error,
+  /// Read the payload anyway, as bytes: the header says `Int8`,
+  /// so a frame is a byte per channel, and the code itself is
+  /// left for `RkReader::unknown_format`. Only the PCM bytes can
+  /// be read then, what goes by samples fails with `RErr::Format`.
+  Passthrough
+}
+
+/// Knobs for `demux_with_options`, the defaults match `demux`.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct DemuxOptions {
+  /// When `Strict`, the first bytes of the payload are also
+  /// peeked and checked against the Zstd magic number, failing
+  /// right away with `RErr::Decompress` if they disagree with
+  /// the header's compression flag. This blocks until four
+  /// bytes of the payload are available.
+  ///
+  /// An uncompressed payload whose first samples happen to
+  /// spell out the magic number is also rejected.
+  pub strictness: Strictness,
+
+  /// Largest Zstd window (as a power of two) the decoder will
+  /// allocate, payloads needing more fail with `RErr::Decompress`.
+  /// `None` accepts any window Zstd supports, so every stream
+  /// this crate writes can be read back; lower it to bound the
+  /// memory spent on untrusted input.
+  pub window_log_max: Option<u32>,
+
+  /// Most bytes `decode_from_slice_with_options` reserves for
+  /// a compressed payload based on the size the stream claims,
+  /// a lie can't make it allocate more than that up-front.
+  /// `None` uses a cap of 16MiB.
+  pub max_prealloc: Option<usize>,
+
+  /// Start code the stream has to begin with, see the field of
+  /// `MuxOptions`, anything else fails with `RErr::StartCode`.
+  /// `None` expects the standard `0x3d`.
+  pub start_code: Option<u8>,
+
+  /// Most bytes of PCM data the payload may decode to, reading
+  /// past them fails with `RErr::LimitExceeded`. A few bytes of
+  /// Zstd can expand to gigabytes, so set this when the input
+  /// isn't trusted. `None` reads on until the payload ends.
+  pub max_decompressed_bytes: Option<u64>,
+
+  /// Bytes of a compressed payload read from the source at a
+  /// time to feed the Zstd decoder. Only throughput depends on
+  /// it, mostly when every read of the source is a system call.
+  /// `None` uses the input size Zstd recommends, about 128KiB.
+  pub read_buffer_size: Option<usize>,
+
+  /// What to do with a header whose format code is reserved,
+  /// such as one written by a later revision of the format.
+  pub on_unknown_format: UnknownFormat
+}
+
+#[cfg(any(test, not(feature = "no_std")))]
+impl DemuxOptions {
+  pub(crate) fn start_code(&self) -> u8 {
+    self.start_code.unwrap_or(crate::header::START_CODE)
+  }
+}
@@ -0,0 +1,84 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+use super::{Hdr, RErr};
+
+/// Writer keeping only the last `frames` frames of the PCM data
+/// written to it, dropping the oldest ones as new ones come in.
+/// This is lossy by design: it's for live sources where only a
+/// recent window matters ("save the last 30 seconds"), which
+/// `snapshot` turns into a stream of its own at any time.
+///
+/// Memory stays at the window plus the largest single write.
+pub struct RingMuxBuffer {
+  hdr: Hdr,
+  frames: usize,
+  buf: VecDeque<u8>
+}
+
+impl RingMuxBuffer {
+  /// Ring of `frames` frames of PCM data laid out as `h` says.
+  /// Fails with `RErr::Channels` if `h` has no channels, as it
+  /// couldn't hold a frame.
+  pub fn new(h: Hdr, frames: usize) -> Result<Self, RErr> {
+    if h.channels == 0 { return Err(RErr::Channels) }
+    Ok(RingMuxBuffer { hdr: h, frames, buf: VecDeque::new() })
+  }
+
+  /// Whole frames currently held, at most the capacity.
+  pub fn frames(&self) -> usize {
+    self.buf.len() / self.hdr.block_align()
+  }
+
+  /// Mux the frames held into a new stream, compressed if `lev`
+  /// is given (see `mux`). The header is the one the ring was
+  /// made with, carrying the number of frames. A partial frame
+  /// at the end, still being written, is left out.
+  pub fn snapshot(&self, lev: Option<u8>) -> Result<Vec<u8>, RErr> {
+    let n = self.frames();
+    let h = Hdr { frames: Some(n as u64), ..self.hdr };
+    let (a, b) = self.buf.as_slices();
+    let len = n * self.hdr.block_align();
+
+    let mut rkout = crate::mux(Vec::new(), h, lev)?;
+    let head = a.len().min(len);
+    if rkout.write_all(&a[..head]).is_err()
+       || rkout.write_all(&b[..len - head]).is_err() { return Err(RErr::IO) }
+
+    rkout.finish()
+  }
+}
+
+impl Write for RingMuxBuffer {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.buf.extend(buf);
+
+    // whole frames go from the front, so it stays aligned.
+    let over = self.frames().saturating_sub(self.frames);
+    self.buf.drain(..over * self.hdr.block_align());
+
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+#[test]
+fn ring_keeps_latest_frames() {
+  use crate::{decode_from_slice, Fmt};
+
+  let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 1, frames: None };
+  assert!(matches!(RingMuxBuffer::new(Hdr { channels: 0, ..h }, 4),
+                   Err(RErr::Channels)));
+
+  let mut ring = RingMuxBuffer::new(h, 4).unwrap();
+  let pcm: Vec<u8> = (0..21).collect();
+  for c in pcm.chunks(3) { ring.write_all(c).unwrap() }
+
+  // frames 6 to 9 are whole, the last byte is half a frame.
+  assert!(ring.frames() == 4);
+  let (dh, out) = decode_from_slice(&ring.snapshot(None).unwrap()).unwrap();
+  assert!(dh.frames == Some(4) && out == pcm[12..20]);
+}
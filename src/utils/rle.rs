@@ -0,0 +1,195 @@
+//! Silence run-length coding, applied to the PCM data ahead of
+//! compression. The coded stream is a sequence of records, each
+//! starting with a little-endian `u32`: with the top bit set the
+//! rest is a number of zero bytes, otherwise it's the number of
+//! literal bytes that follow.
+use std::io::{self, Read, Write};
+
+/// Top bit of a record's length, marking a run of zeros.
+const ZERO_RUN: u32 = 1 << 31;
+
+/// Most bytes in a literal record, what's buffered up to them.
+const MAX_LITERAL: usize = 1 << 16;
+
+/// Fewest silent frames worth a record of their own.
+const MIN_RUN: u64 = 32;
+
+/// Collapses runs of silent frames as the PCM data goes by.
+pub(crate) struct Rle {
+  ba: usize,
+  /// Bytes of a frame split across two writes.
+  pending: Vec<u8>,
+  lit: Vec<u8>,
+  /// Silent frames seen since the last literal one.
+  zeros: u64
+}
+
+impl Rle {
+  pub(crate) fn new(block_align: usize) -> Self {
+    Rle { ba: block_align, pending: Vec::new(), lit: Vec::new(), zeros: 0 }
+  }
+
+  pub(crate) fn push<W: Write>(&mut self, buf: &[u8], w: &mut W)
+    -> io::Result<()> {
+    let mut buf = buf;
+
+    if !self.pending.is_empty() {
+      let n = (self.ba - self.pending.len()).min(buf.len());
+      self.pending.extend_from_slice(&buf[..n]);
+      buf = &buf[n..];
+
+      if self.pending.len() < self.ba { return Ok(()) }
+      let f = std::mem::take(&mut self.pending);
+      self.frame(&f, w)?;
+    }
+
+    let mut frames = buf.chunks_exact(self.ba);
+    for f in &mut frames { self.frame(f, w)? }
+    self.pending.extend_from_slice(frames.remainder());
+
+    Ok(())
+  }
+
+  fn frame<W: Write>(&mut self, f: &[u8], w: &mut W) -> io::Result<()> {
+    if f.iter().all(|&b| b == 0) {
+      self.zeros += 1;
+      return Ok(())
+    }
+
+    self.end_run(w)?;
+    self.lit.extend_from_slice(f);
+    if self.lit.len() >= MAX_LITERAL { self.literal(w)? }
+
+    Ok(())
+  }
+
+  /// Emit the silent frames seen so far, as literals if too few.
+  fn end_run<W: Write>(&mut self, w: &mut W) -> io::Result<()> {
+    if self.zeros < MIN_RUN {
+      let n = self.zeros as usize * self.ba;
+      self.lit.resize(self.lit.len() + n, 0);
+    } else {
+      self.literal(w)?;
+
+      let mut n = self.zeros * self.ba as u64;
+      while n > 0 {
+        let run = n.min((ZERO_RUN - 1) as u64);
+        w.write_all(&(ZERO_RUN | run as u32).to_le_bytes())?;
+        n -= run;
+      }
+    }
+
+    self.zeros = 0;
+    Ok(())
+  }
+
+  fn literal<W: Write>(&mut self, w: &mut W) -> io::Result<()> {
+    for c in self.lit.chunks(MAX_LITERAL) {
+      w.write_all(&(c.len() as u32).to_le_bytes())?;
+      w.write_all(c)?;
+    }
+
+    self.lit.clear();
+    Ok(())
+  }
+
+  /// Bytes of a partial frame held back from the records.
+  #[cfg_attr(not(feature = "compression"), allow(dead_code))]
+  pub(crate) fn pending(&self) -> usize {
+    self.pending.len()
+  }
+
+  /// Write out everything but a partial frame, so the records
+  /// so far can be decoded on their own.
+  pub(crate) fn flush<W: Write>(&mut self, w: &mut W) -> io::Result<()> {
+    self.end_run(w)?;
+    self.literal(w)
+  }
+
+  /// Write out everything, a trailing partial frame included.
+  pub(crate) fn finish<W: Write>(&mut self, w: &mut W) -> io::Result<()> {
+    self.end_run(w)?;
+    let p = std::mem::take(&mut self.pending);
+    self.lit.extend_from_slice(&p);
+    self.literal(w)
+  }
+}
+
+/// Expands the records written by `Rle` back to PCM data.
+#[derive(Default)]
+pub(crate) struct Unrle {
+  literal: u32,
+  zeros: u32,
+  /// Bytes of the next record read so far, kept across reads so
+  /// a `WouldBlock` partway through one can be retried.
+  rec: [u8; 4],
+  got: usize
+}
+
+impl Unrle {
+  pub(crate) fn read<R: Read>(&mut self, r: &mut R, buf: &mut [u8])
+    -> io::Result<usize> {
+    if buf.is_empty() { return Ok(0) }
+
+    while self.literal == 0 && self.zeros == 0 {
+      while self.got < 4 {
+        match r.read(&mut self.rec[self.got..]) {
+          Ok(0) if self.got == 0 => return Ok(0),
+          Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+          Ok(k) => self.got += k,
+          Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+          Err(e) => return Err(e)
+        }
+      }
+
+      self.got = 0;
+      let n = u32::from_le_bytes(self.rec);
+      match n & ZERO_RUN {
+        0 => self.literal = n,
+        _ => self.zeros = n & !ZERO_RUN
+      }
+    }
+
+    if self.zeros > 0 {
+      let n = buf.len().min(self.zeros as usize);
+      buf[..n].iter_mut().for_each(|b| *b = 0);
+      self.zeros -= n as u32;
+      return Ok(n)
+    }
+
+    let n = buf.len().min(self.literal as usize);
+    let n = r.read(&mut buf[..n])?;
+    if n == 0 { return Err(io::ErrorKind::UnexpectedEof.into()) }
+
+    self.literal -= n as u32;
+    Ok(n)
+  }
+}
+
+#[test]
+fn rle_roundtrip() {
+  let mut pcm = vec![0u8; 4000];
+  pcm.extend((0..1000u32).map(|i| (i % 200) as u8 + 1));
+  pcm.extend(vec![0u8; 40]);
+  pcm.extend(vec![0u8; 100_000]);
+  pcm.push(9);
+
+  let mut z = Vec::new();
+  let mut rle = Rle::new(4);
+  for c in pcm.chunks(777) { rle.push(c, &mut z).unwrap() }
+  rle.finish(&mut z).unwrap();
+  assert!(z.len() < 2000);
+
+  let mut out = Vec::new();
+  let mut un = Unrle::default();
+  let mut r = &z[..];
+  let mut buf = [0u8; 100];
+  loop {
+    match un.read(&mut r, &mut buf).unwrap() {
+      0 => break,
+      n => out.extend_from_slice(&buf[..n])
+    }
+  }
+
+  assert!(out == pcm);
+}
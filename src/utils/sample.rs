@@ -0,0 +1,52 @@
+use super::Fmt;
+
+mod sealed { pub trait Sealed {} }
+
+/// Native Rust type of a sampleformat, coded to bytes in
+/// little-endian order on the wire. It's what the generic
+/// sample APIs, like `RkWriter::write_native`, are bound by.
+/// Only the six types with a format implement it.
+pub trait Sample: Copy + sealed::Sealed {
+  /// Sampleformat coding samples of this type.
+  const FMT: Fmt;
+
+  /// Append the sample's little-endian bytes to `out`.
+  fn put(self, out: &mut Vec<u8>);
+  /// Sample out of exactly `FMT.size()` little-endian bytes.
+  fn get(b: &[u8]) -> Self;
+}
+
+macro_rules! sample {
+  ($($t:ty => $f:ident),*) => { $(
+    impl sealed::Sealed for $t {}
+
+    impl Sample for $t {
+      const FMT: Fmt = Fmt::$f;
+
+      fn put(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes())
+      }
+
+      fn get(b: &[u8]) -> Self {
+        let mut a = [0u8; std::mem::size_of::<$t>()];
+        a.copy_from_slice(b);
+        <$t>::from_le_bytes(a)
+      }
+    }
+  )* }
+}
+
+sample!(i8  => Int8,    i16 => Int16,
+        i32 => Int32,   i64 => Int64,
+        f32 => Float32, f64 => Float64);
+
+#[test]
+fn sample_formats() {
+  fn fmt_of<T: Sample>() -> Fmt { T::FMT }
+
+  assert!(<i16 as Sample>::FMT == Fmt::Int16);
+  assert!(fmt_of::<f64>() == Fmt::Float64);
+  assert!(fmt_of::<i8>().sample_type_name() == "i8");
+  assert!(fmt_of::<i64>().sample_type_name() == "i64");
+  assert!(fmt_of::<f32>().sample_type_name() == "f32");
+}
@@ -0,0 +1,69 @@
+//! Helpers shared by the tests.
+use super::{conv::encode_f64, Fmt, Hdr};
+
+/// Assert `a` and `b` hold the same samples give or take `eps`,
+/// for checking lossy paths (conversion, resampling) that can't
+/// be compared byte for byte.
+pub(crate) fn assert_samples_close(a: &[f32], b: &[f32], eps: f32) {
+  assert!(a.len() == b.len(), "lengths differ: {} != {}", a.len(), b.len());
+
+  for (i, (x, y)) in a.iter().zip(b).enumerate() {
+    assert!((x - y).abs() <= eps,
+            "sample {} differs: {} vs {} (eps {})", i, x, y, eps);
+  }
+}
+
+/// Split little-endian `Float32` bytes back into samples.
+pub(crate) fn f32s(b: &[u8]) -> Vec<f32> {
+  b.chunks_exact(4)
+   .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+   .collect()
+}
+
+/// Full-scale sine wave of `freq` Hz, `frames` long, coded as
+/// `h` says with every channel carrying the same samples.
+pub(crate) fn generate_tone(freq: f64, h: &Hdr, frames: u64) -> Vec<u8> {
+  let step = 2.0 * std::f64::consts::PI * freq / h.rate as f64;
+  let s: Vec<f64> = (0..frames)
+    .flat_map(|i| std::iter::repeat_n((i as f64 * step).sin(),
+                                      h.channels as usize))
+    .collect();
+
+  let mut out = Vec::new();
+  encode_f64(&s, h.format, &mut out);
+  out
+}
+
+/// Levels to run a test at: uncompressed, and compressed too
+/// if the `compression` feature is on.
+pub(crate) fn levels() -> Vec<Option<u8>> {
+  let mut levels = vec![None];
+  if cfg!(feature = "compression") { levels.push(Some(3)) }
+  levels
+}
+
+#[test]
+fn tone_peaks_at_full_scale() {
+  use super::sample::Sample;
+
+  fn peak<T: Sample + PartialOrd>(b: &[u8]) -> T {
+    let n = std::mem::size_of::<T>();
+    b.chunks_exact(n).map(T::get)
+     .fold(T::get(&b[..n]), |m, x| if x > m { x } else { m })
+  }
+
+  for &format in Fmt::all() {
+    let h = Hdr { format, rate: 8000, channels: 2, frames: None };
+    let tone = generate_tone(2000.0, &h, 100);
+    assert!(tone.len() == 100 * h.block_align());
+
+    match format {
+      Fmt::Int8    => assert!(peak::<i8>(&tone) == i8::MAX),
+      Fmt::Int16   => assert!(peak::<i16>(&tone) == i16::MAX),
+      Fmt::Int32   => assert!(peak::<i32>(&tone) == i32::MAX),
+      Fmt::Int64   => assert!(peak::<i64>(&tone) == i64::MAX),
+      Fmt::Float32 => assert!(peak::<f32>(&tone) == 1.0),
+      Fmt::Float64 => assert!(peak::<f64>(&tone) == 1.0)
+    }
+  }
+}
@@ -0,0 +1,157 @@
+use std::convert::TryFrom;
+use std::io::{self, Read, Take};
+
+use crate::{Fmt, Hdr, RErr};
+
+/// `WAVE_FORMAT_*` codes of the `fmt ` chunk that can be read.
+const WAV_PCM: u16 = 1;
+const WAV_FLOAT: u16 = 3;
+const WAV_EXTENSIBLE: u16 = 0xfffe;
+
+/// Reads the PCM data of a WAV file as RKPI2 codes it, so that
+/// it can go straight into a writer muxed with `header`. Only
+/// the `data` chunk is read, and 8-bit samples, which WAV stores
+/// unsigned, are turned into `Int8` ones.
+pub struct WavReader<R: Read> {
+    inner: Take<R>,
+    hdr: Hdr,
+}
+
+impl<R: Read> WavReader<R> {
+    /// Header the PCM data fits, with the number of frames the
+    /// `data` chunk holds.
+    pub fn header(&self) -> &Hdr {
+        &self.hdr
+    }
+}
+
+impl<R: Read> Read for WavReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if self.hdr.format == Fmt::Int8 {
+            buf[..n].iter_mut().for_each(|b| *b ^= 0x80);
+        }
+
+        Ok(n)
+    }
+}
+
+/// Parse the chunks of a WAV file from `r` up to its `data`
+/// chunk, skipping any it doesn't need. Fails with
+/// `RErr::StartCode` if it isn't a RIFF/WAVE file, and with
+/// `RErr::Format` for samples that have no sampleformat (see
+/// `Fmt::from_parts`), such as 24-bit or compressed ones.
+/// The samplerate and the channels are checked when muxing.
+pub fn from_wav<R: Read>(r: R) -> Result<WavReader<R>, RErr> {
+    let mut r = r;
+    let riff = read_array::<_, 12>(&mut r)?;
+    if &riff[..4] != b"RIFF" || &riff[8..] != b"WAVE" {
+        return Err(RErr::StartCode)
+    }
+
+    let mut hdr = None;
+    loop {
+        let chunk = read_array::<_, 8>(&mut r)?;
+        let len = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+
+        match &chunk[..4] {
+            b"fmt " => {
+                if len < 16 { return Err(RErr::Format) }
+                let mut fmt = vec![0u8; len as usize];
+                r.read_exact(&mut fmt).map_err(RErr::from)?;
+                hdr = Some(parse_fmt(&fmt)?);
+            },
+            b"data" => {
+                let h: Hdr = hdr.ok_or(RErr::Format)?;
+                let frames = len as u64 / h.block_align() as u64;
+                let inner = r.take(frames * h.block_align() as u64);
+                return Ok(WavReader { inner,
+                                      hdr: Hdr { frames: Some(frames), ..h } })
+            },
+            _ => {
+                // chunks are padded to an even length.
+                let skip = len as u64 + (len & 1) as u64;
+                let n = io::copy(&mut r.by_ref().take(skip), &mut io::sink())
+                    .map_err(RErr::from)?;
+                if n < skip { return Err(RErr::Truncated) }
+            }
+        }
+    }
+}
+
+fn parse_fmt(fmt: &[u8]) -> Result<Hdr, RErr> {
+    let u16_at = |i: usize| u16::from_le_bytes([fmt[i], fmt[i + 1]]);
+    let tag = match u16_at(0) {
+        WAV_EXTENSIBLE if fmt.len() >= 26 => u16_at(24),
+        tag => tag
+    };
+
+    let float = match tag {
+        WAV_PCM => false,
+        WAV_FLOAT => true,
+        _ => return Err(RErr::Format)
+    };
+    let format = Fmt::from_parts(u16_at(14) as u32, float)
+        .ok_or(RErr::Format)?;
+    let channels = u8::try_from(u16_at(2)).map_err(|_| RErr::Channels)?;
+    if channels == 0 { return Err(RErr::Channels) }
+
+    let rate = u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]);
+    Ok(Hdr { format, rate, channels, frames: None })
+}
+
+fn read_array<R: Read, const N: usize>(r: &mut R) -> Result<[u8; N], RErr> {
+    let mut b = [0u8; N];
+    r.read_exact(&mut b).map_err(RErr::from)?;
+    Ok(b)
+}
+
+/// A WAV file with the samples `pcm` in `format`, as WAV codes
+/// them, and an extra chunk ahead of the `fmt ` one.
+#[cfg(test)]
+pub(crate) fn wav_file(format: Fmt, rate: u32, channels: u16, pcm: &[u8])
+    -> Vec<u8> {
+    let tag = if format.is_float() { WAV_FLOAT } else { WAV_PCM };
+    let bits = format.bits() as u16;
+    let align = channels * bits / 8;
+
+    let mut f = Vec::new();
+    f.extend_from_slice(b"RIFF");
+    f.extend_from_slice(&(4 + 14 + 24 + 8 + pcm.len() as u32).to_le_bytes());
+    f.extend_from_slice(b"WAVE");
+    f.extend_from_slice(b"junk\x05\0\0\0hello\0");
+    f.extend_from_slice(b"fmt \x10\0\0\0");
+    for x in [tag, channels].iter() { f.extend_from_slice(&x.to_le_bytes()) }
+    f.extend_from_slice(&rate.to_le_bytes());
+    f.extend_from_slice(&(rate * align as u32).to_le_bytes());
+    for x in [align, bits].iter() { f.extend_from_slice(&x.to_le_bytes()) }
+    f.extend_from_slice(b"data");
+    f.extend_from_slice(&(pcm.len() as u32).to_le_bytes());
+    f.extend_from_slice(pcm);
+    f
+}
+
+#[test]
+fn wav_to_pcm() {
+    let pcm: Vec<u8> = (0..24).collect();
+    let file = wav_file(Fmt::Int16, 22050, 2, &pcm);
+
+    let mut wav = from_wav(&file[..]).unwrap();
+    assert!(*wav.header() == Hdr { format: Fmt::Int16, rate: 22050,
+                                   channels: 2, frames: Some(6) });
+    let mut out = Vec::new();
+    wav.read_to_end(&mut out).unwrap();
+    assert!(out == pcm);
+
+    // unsigned 8-bit samples come out signed.
+    let file = wav_file(Fmt::Int8, 8000, 1, &[0x00, 0x80, 0xff]);
+    let mut out = Vec::new();
+    from_wav(&file[..]).unwrap().read_to_end(&mut out).unwrap();
+    assert!(out == [0x80, 0x00, 0x7f]);
+
+    let mut odd = wav_file(Fmt::Int16, 8000, 1, &[]);
+    odd[48] = 24;
+    assert!(matches!(from_wav(&odd[..]), Err(RErr::Format)));
+    assert!(matches!(from_wav(&b"RIFF\0\0\0\0AVI "[..]), Err(RErr::StartCode)));
+    assert!(matches!(from_wav(&file[..30]), Err(RErr::Truncated)));
+}
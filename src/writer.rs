@@ -0,0 +1,687 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Seek, SeekFrom, Write};
+#[cfg(feature = "compression")]
+use zstd::Encoder;
+
+use crate::{header, Codec, Hdr, MuxOptions, RErr, SeekIndex};
+use crate::header::Coding;
+use crate::utils::conv::encode_f32;
+use crate::utils::crc::Crc32;
+use crate::utils::delta::Delta;
+use crate::utils::rle::Rle;
+use crate::utils::sample::Sample;
+
+/// PCM sink returned by `mux`, the header has already been
+/// written and everything written here is the payload.
+///
+/// It has to be consumed with `finish`: dropping it instead
+/// cuts the stream short of whatever `finish` has left to write,
+/// such as the end of the Zstd frame, so it's unreadable past
+/// the last `flush_frame`. Debug builds panic when a writer
+/// with such an ending is dropped unfinished, unless it's on
+/// purpose with `abandon`, or after a write to it failed.
+#[must_use = "the stream is incomplete until `finish` is called"]
+pub struct RkWriter<W: Write> {
+    sink: Sink<W>,
+    hdr: Hdr,
+    hdr_len: usize,
+    opts: MuxOptions,
+    /// Bytes of PCM data written so far.
+    written: u64,
+    /// Collapses silence ahead of the sink, if asked for.
+    rle: Option<Rle>,
+    /// CRC of the PCM data, to go after it.
+    crc: Option<Crc32>,
+    /// Differences samples ahead of everything else, if asked for.
+    delta: Option<Delta>,
+    /// Where the header starts and how to patch in the frame
+    /// count there, set by `mux_seekable` for backfilling.
+    backfill: Option<(u64, Patch<W>)>,
+    /// Starts of the Zstd frames after the first.
+    index: SeekIndex,
+    guard: Unfinished,
+}
+
+/// Asserts in debug builds that it isn't dropped armed, that's
+/// when the writer it's in is dropped with something left for
+/// `finish`.
+struct Unfinished(bool);
+
+impl Drop for Unfinished {
+    fn drop(&mut self) {
+        debug_assert!(!self.0 || std::thread::panicking(),
+                      "RkWriter dropped without calling finish(), \
+                       the stream it wrote is incomplete");
+    }
+}
+
+/// Overwrite the frame count of the header at an offset.
+type Patch<W> = fn(&mut W, u64, u64) -> io::Result<()>;
+
+fn patch<W: Write + Seek>(w: &mut W, at: u64, frames: u64) -> io::Result<()> {
+    let end = w.stream_position()?;
+
+    // the count is the last field of the 12 byte header.
+    w.seek(SeekFrom::Start(at + 4))?;
+    w.write_all(&frames.to_le_bytes())?;
+    w.seek(SeekFrom::Start(end))?;
+    Ok(())
+}
+
+enum Sink<W: Write> {
+    Raw(W),
+    #[cfg(feature = "compression")]
+    /// The encoder is only ever `None` in the middle of
+    /// `flush_frame` or after a failed frame restart.
+    Zstd(Option<Encoder<Counted<W>>>),
+    /// Payload held back for `MuxOptions::auto_store`, `None`
+    /// only after failing to set up the sink it decided on.
+    #[cfg(feature = "compression")]
+    Trial(Option<Trial<W>>),
+}
+
+/// Start of the payload, with the header to go ahead of it once
+/// it's known whether compressing pays off.
+#[cfg(feature = "compression")]
+struct Trial<W: Write> {
+    w: W,
+    /// Header flagged as compressed, empty in footer mode.
+    hdr: Vec<u8>,
+    buf: Vec<u8>,
+    opts: MuxOptions,
+}
+
+/// Least `estimate_ratio` of the held back payload that's worth
+/// compressing it for.
+#[cfg(feature = "compression")]
+const AUTO_STORE_RATIO: f64 = 1.0 / 0.95;
+
+/// Compressed output, counted for the `SeekIndex`.
+#[cfg(feature = "compression")]
+struct Counted<W: Write> {
+    w: W,
+    n: u64,
+}
+
+#[cfg(feature = "compression")]
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.w.write(buf)?;
+        self.n += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// Start a Zstd frame over `w` as configured by `opts`.
+#[cfg(feature = "compression")]
+fn encoder<W: Write>(w: Counted<W>, opts: &MuxOptions)
+    -> Result<Encoder<Counted<W>>, RErr> {
+    let lev = opts.level.unwrap_or(0) as i32;
+    let mut e = Encoder::new(w, lev).map_err(|_| RErr::IO)?;
+
+    if opts.zstd_checksum {
+        e.include_checksum(true).map_err(|_| RErr::IO)?;
+    }
+
+    Ok(e)
+}
+
+impl<W: Write> RkWriter<W> {
+    pub(crate) fn new(w: W, hdr: Hdr, hdr_len: usize, opts: MuxOptions)
+        -> Result<Self, RErr> {
+        let sink = match opts.level {
+            #[cfg(feature = "compression")]
+            Some(_) if opts.auto_store => Sink::Trial(Some(Trial {
+                w, hdr: Vec::new(), buf: Vec::new(), opts })),
+            #[cfg(feature = "compression")]
+            Some(_) => Sink::Zstd(Some(encoder(Counted { w, n: 0 }, &opts)?)),
+            #[cfg(not(feature = "compression"))]
+            Some(_) => return Err(RErr::CompressionUnsupported),
+            None => Sink::Raw(w)
+        };
+
+        let rle = match opts.silence_rle {
+            true => Some(Rle::new(hdr.block_align())),
+            false => None
+        };
+
+        let crc = if opts.pcm_crc { Some(Crc32::default()) } else { None };
+        let delta = match opts.coding(&hdr).delta {
+            true => Some(Delta::new(hdr.format.size(), hdr.channels as usize)),
+            false => None
+        };
+
+        // a plain payload is complete as written, nothing else is.
+        let guard = Unfinished(opts.level.is_some() || opts.silence_rle
+                               || opts.pcm_crc || opts.footer
+                               || opts.backfill_frames);
+
+        Ok(RkWriter { sink, hdr, hdr_len, opts, written: 0, rle, crc, delta,
+                      backfill: None, index: SeekIndex::default(), guard })
+    }
+
+    /// Have `hdr` written ahead of the payload once `auto_store`
+    /// decides on compressing it or not, rather than up-front.
+    #[cfg_attr(not(feature = "compression"), allow(unused_variables, unused_mut))]
+    pub(crate) fn hold_header(mut self, hdr: Vec<u8>) -> Self {
+        #[cfg(feature = "compression")]
+        if let Sink::Trial(Some(t)) = &mut self.sink { t.hdr = hdr }
+        self
+    }
+
+    /// Header the stream was muxed with.
+    pub fn header(&self) -> &Hdr {
+        &self.hdr
+    }
+
+    /// Number of bytes the header took in the underlying writer,
+    /// the payload starts right after them.
+    pub fn header_len(&self) -> usize {
+        self.hdr_len
+    }
+
+    /// Codec the payload is being coded with.
+    pub fn codec(&self) -> Codec {
+        match self.sink {
+            #[cfg(feature = "compression")]
+            Sink::Zstd(..) | Sink::Trial(_) => Codec::Zstd,
+            Sink::Raw(_) => Codec::None
+        }
+    }
+
+    /// Whether the payload is being compressed.
+    pub fn compressed(&self) -> bool {
+        self.codec() != Codec::None
+    }
+
+    /// End the current Zstd frame and start a new one, so a
+    /// decoder joining the stream at this point can start
+    /// decoding from here. No-op for uncompressed streams.
+    ///
+    /// Every frame starts with an empty compression window, so
+    /// calling this often hurts the compression ratio, roughly
+    /// in proportion to how small the frames become.
+    ///
+    /// Where the new frame starts is added to the `seek_index`.
+    pub fn flush_frame(&mut self) -> Result<(), RErr> {
+        #[cfg(feature = "compression")]
+        self.sink.decide().map_err(|_| RErr::IO)?;
+
+        #[cfg(feature = "compression")]
+        if let Sink::Zstd(enc) = &mut self.sink {
+            let mut pcm = self.written;
+            if let (Some(rle), Some(e)) = (&mut self.rle, enc.as_mut()) {
+                rle.flush(e).map_err(|_| RErr::IO)?;
+                pcm -= rle.pending() as u64;
+            }
+
+            let w = match enc.take() {
+                Some(e) => e.finish().map_err(|_| RErr::IO)?,
+                None => return Err(RErr::IO)
+            };
+
+            self.index.push(pcm, w.n);
+            *enc = Some(encoder(w, &self.opts)?);
+        }
+
+        Ok(())
+    }
+
+    /// Starts of the Zstd frames `flush_frame` began so far, to
+    /// give `demux_indexed` for seeking back into the stream, or
+    /// to store at its end with `MuxOptions::index_interval`.
+    /// Always empty for an uncompressed one, which needs none.
+    pub fn seek_index(&self) -> &SeekIndex {
+        &self.index
+    }
+
+    /// Write samples of the native type of the stream's format,
+    /// so `i16` for `Int16` and so on, failing with `RErr::Format`
+    /// for any other. They're coded in the format's little-endian
+    /// order whatever the host's is, swapping their bytes on
+    /// big-endian hosts, where passing the in-memory bytes of the
+    /// samples to `write` would get it wrong.
+    pub fn write_native<T: Sample>(&mut self, s: &[T]) -> Result<(), RErr> {
+        if self.hdr.format != T::FMT { return Err(RErr::Format) }
+
+        let mut buf = Vec::with_capacity(s.len() * T::FMT.size());
+        for &x in s { x.put(&mut buf) }
+
+        self.write_all(&buf).map_err(|_| RErr::IO)
+    }
+
+    /// Write `Int8` samples, fails with `RErr::Format` if the
+    /// stream was muxed with a different sampleformat. The
+    /// other typed writers below work the same way, they're all
+    /// `write_native` for one type.
+    pub fn write_i8_samples(&mut self, s: &[i8]) -> Result<(), RErr> {
+        self.write_native(s)
+    }
+
+    /// Write little-endian `Int16` samples.
+    pub fn write_i16_samples(&mut self, s: &[i16]) -> Result<(), RErr> {
+        self.write_native(s)
+    }
+
+    /// Write little-endian `Int32` samples.
+    pub fn write_i32_samples(&mut self, s: &[i32]) -> Result<(), RErr> {
+        self.write_native(s)
+    }
+
+    /// Write little-endian `Int64` samples.
+    pub fn write_i64_samples(&mut self, s: &[i64]) -> Result<(), RErr> {
+        self.write_native(s)
+    }
+
+    /// Write little-endian `Float32` samples.
+    pub fn write_f32_samples(&mut self, s: &[f32]) -> Result<(), RErr> {
+        self.write_native(s)
+    }
+
+    /// Write little-endian `Float64` samples.
+    pub fn write_f64_samples(&mut self, s: &[f64]) -> Result<(), RErr> {
+        self.write_native(s)
+    }
+
+    /// Drop the writer without finishing the stream, leaving it
+    /// cut short, as when giving up on it over an error elsewhere.
+    /// Unlike dropping it, this doesn't panic in debug builds.
+    pub fn abandon(mut self) {
+        self.guard.0 = false;
+    }
+
+    /// Finish the payload (ending the Zstd frame when compressing)
+    /// and return the underlying writer. With frame count backfill
+    /// the header is patched up, and the writer is left at the
+    /// end of the stream. In footer mode the header goes last.
+    #[cfg_attr(not(feature = "compression"),
+               allow(clippy::infallible_destructuring_match))]
+    pub fn finish(mut self) -> Result<W, RErr> {
+        self.guard.0 = false;
+
+        if let Some(crc) = self.crc {
+            // filtered like the samples, readers undo both together.
+            let crc = crc.value().to_le_bytes();
+            let crc = match &mut self.delta {
+                Some(d) => d.encode(&crc),
+                None => &crc[..]
+            };
+            let res = match &mut self.rle {
+                Some(rle) => rle.push(crc, &mut self.sink),
+                None => self.sink.write_all(crc)
+            };
+            if res.is_err() { return Err(RErr::IO) }
+        }
+
+        if let Some(rle) = &mut self.rle {
+            rle.finish(&mut self.sink).map_err(|_| RErr::IO)?;
+        }
+
+        #[cfg(feature = "compression")]
+        self.sink.decide().map_err(|_| RErr::IO)?;
+        let codec = self.codec();
+
+        let mut w = match self.sink {
+            Sink::Raw(w) => w,
+            #[cfg(feature = "compression")]
+            Sink::Zstd(Some(e)) => {
+                let mut w = e.finish().map_err(|_| RErr::IO)?.w;
+                let indexed = self.opts.index_interval.is_some()
+                              && !self.opts.footer;
+                if indexed && w.write_all(&self.index.to_footer()).is_err() {
+                    return Err(RErr::IO)
+                }
+                w
+            },
+            #[cfg(feature = "compression")]
+            Sink::Zstd(None) | Sink::Trial(_) => return Err(RErr::IO)
+        };
+
+        let frames = self.hdr.frames_in(self.written);
+        if let Some((at, patch)) = self.backfill {
+            if patch(&mut w, at, frames).is_err() { return Err(RErr::IO) }
+        }
+
+        if self.opts.footer {
+            let h = Hdr { frames: Some(frames), ..self.hdr };
+            let c = Coding { codec, ..self.opts.coding(&h) };
+            let hdr = header::encode(&h, c, self.opts.start_code())?;
+            if w.write_all(&hdr).is_err() { return Err(RErr::IO) }
+        }
+
+        Ok(w)
+    }
+}
+
+impl<W: Write + Seek> RkWriter<W> {
+    pub(crate) fn backfill_at(&mut self, at: u64) {
+        self.backfill = Some((at, patch::<W>));
+    }
+}
+
+impl<W: Write> AsRef<Hdr> for RkWriter<W> {
+    fn as_ref(&self) -> &Hdr {
+        &self.hdr
+    }
+}
+
+#[cfg(feature = "compression")]
+impl<W: Write> Sink<W> {
+    /// Go on as a plain or Zstd sink if still on trial, writing
+    /// the header and then what was held back.
+    fn decide(&mut self) -> io::Result<()> {
+        let t = match self {
+            Sink::Trial(t) => t.take().ok_or_else(broken)?,
+            _ => return Ok(())
+        };
+
+        let lev = t.opts.level.unwrap_or(0);
+        let store = crate::estimate_ratio(&t.buf, lev) < AUTO_STORE_RATIO;
+        let (mut w, mut hdr) = (t.w, t.hdr);
+        if !hdr.is_empty() { header::set_compressed(&mut hdr, !store) }
+        w.write_all(&hdr)?;
+
+        *self = match store {
+            true => Sink::Raw(w),
+            false => Sink::Zstd(Some(encoder(Counted { w, n: 0 }, &t.opts)?))
+        };
+        self.write_all(&t.buf)
+    }
+}
+
+impl<W: Write> Write for Sink<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Raw(w) => w.write(buf),
+            #[cfg(feature = "compression")]
+            Sink::Zstd(Some(e)) => e.write(buf),
+            #[cfg(feature = "compression")]
+            Sink::Zstd(None) | Sink::Trial(None) => Err(broken()),
+            #[cfg(feature = "compression")]
+            Sink::Trial(Some(t)) => {
+                let n = buf.len().min(crate::RATIO_SAMPLE - t.buf.len());
+                t.buf.extend_from_slice(&buf[..n]);
+                if t.buf.len() == crate::RATIO_SAMPLE { self.decide()? }
+                Ok(n)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Raw(w) => w.flush(),
+            #[cfg(feature = "compression")]
+            Sink::Zstd(Some(e)) => e.flush(),
+            #[cfg(feature = "compression")]
+            Sink::Zstd(None) => Err(broken()),
+            #[cfg(feature = "compression")]
+            Sink::Trial(_) => { self.decide()?; self.flush() }
+        }
+    }
+}
+
+/// Shows what's been written, never touching the sink, like
+/// the `Debug` of `RkReader`.
+impl<W: Write> fmt::Debug for RkWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RkWriter")
+         .field("hdr", &self.hdr)
+         .field("compressed", &self.compressed())
+         .field("frames_written", &self.hdr.frames_in(self.written))
+         .finish()
+    }
+}
+
+impl<W: Write> Write for RkWriter<W> {
+    /// A failed write, or one taking nothing, leaves the stream
+    /// broken anyway, so the writer can be dropped unfinished
+    /// after one.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let res = self.write_payload(buf);
+        match res {
+            Ok(0) if !buf.is_empty() => self.guard.0 = false,
+            Err(_) => self.guard.0 = false,
+            _ => ()
+        }
+        res
+    }
+
+    /// Silent runs are cut short here, so that everything written
+    /// so far gets through.
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(rle) = &mut self.rle { rle.flush(&mut self.sink)? }
+        self.sink.flush()
+    }
+}
+
+impl<W: Write> RkWriter<W> {
+    fn write_payload(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // stop at the next interval, to end the Zstd frame there.
+        let mark: Option<u64> = match (&self.sink, self.opts.index_interval) {
+            #[cfg(feature = "compression")]
+            (Sink::Zstd(_), Some(n)) if n > 0 => {
+                let every = n.saturating_mul(self.hdr.block_align() as u64);
+                Some((self.written / every).saturating_add(1)
+                         .saturating_mul(every))
+            },
+            _ => None
+        };
+        let buf = match mark {
+            Some(m) => &buf[..(m - self.written).min(buf.len() as u64) as usize],
+            None => buf
+        };
+
+        let n = match (&mut self.delta, &mut self.rle) {
+            (Some(d), Some(rle)) => { rle.push(d.encode(buf), &mut self.sink)?;
+                                      buf.len() },
+            (Some(d), None) => { self.sink.write_all(d.encode(buf))?; buf.len() },
+            (None, Some(rle)) => { rle.push(buf, &mut self.sink)?; buf.len() },
+            (None, None) => self.sink.write(buf)?
+        };
+
+        if let Some(crc) = &mut self.crc { crc.update(&buf[..n]) }
+        self.written += n as u64;
+
+        if mark == Some(self.written) { self.flush_frame()? }
+        Ok(n)
+    }
+}
+
+/// Interleaves float samples pushed one channel at a time into
+/// an `RkWriter`, for producers with a source per channel. The
+/// channels can run ahead of each other, samples are buffered
+/// until every channel has some for a frame, and only whole
+/// frames are converted and written.
+pub struct ChannelMuxer<W: Write> {
+    w: RkWriter<W>,
+    queued: Vec<VecDeque<f32>>,
+}
+
+impl<W: Write> ChannelMuxer<W> {
+    /// Interleave into `w`, with a queue for every channel of
+    /// its header.
+    pub fn new(w: RkWriter<W>) -> Self {
+        let queued = vec![VecDeque::new(); w.hdr.channels as usize];
+        ChannelMuxer { w, queued }
+    }
+
+    /// Add samples to the end of `channel`, failing with
+    /// `RErr::Channels` if the header has no such channel.
+    pub fn push(&mut self, channel: usize, s: &[f32]) -> Result<(), RErr> {
+        match self.queued.get_mut(channel) {
+            Some(q) => q.extend(s),
+            None => return Err(RErr::Channels)
+        }
+
+        let n = self.queued.iter().map(|q| q.len()).min().unwrap_or(0);
+        if n == 0 { return Ok(()) }
+
+        let mut frames = Vec::with_capacity(n * self.queued.len());
+        for i in 0..n { frames.extend(self.queued.iter().map(|q| q[i])) }
+
+        // the frames stay queued unless they've been written.
+        let mut out = Vec::new();
+        encode_f32(&frames, self.w.hdr.format, &mut out);
+        self.w.write_all(&out).map_err(RErr::from)?;
+        self.queued.iter_mut().for_each(|q| { q.drain(..n); });
+        Ok(())
+    }
+
+    /// Number of samples queued, waiting for the other channels
+    /// to catch up with them into frames.
+    pub fn pending(&self) -> usize {
+        self.queued.iter().map(|q| q.len()).sum()
+    }
+
+    /// Drop the samples that are queued, so `finish` goes through
+    /// with only the frames written so far.
+    pub fn discard_pending(&mut self) {
+        self.queued.iter_mut().for_each(|q| q.clear())
+    }
+
+    /// Finish the stream, see `RkWriter::finish`. With samples
+    /// still `pending` it fails with `RErr::Channels` right away,
+    /// as they can't make up a frame, and the stream is left
+    /// unfinished: check `pending` first, and catch the channels
+    /// up or `discard_pending`, to keep it.
+    pub fn finish(self) -> Result<W, RErr> {
+        if self.pending() > 0 {
+            self.w.abandon();
+            return Err(RErr::Channels)
+        }
+        self.w.finish()
+    }
+}
+
+#[cfg(feature = "compression")]
+fn broken() -> io::Error {
+    io::Error::other("zstd frame could not be restarted")
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn flush_frame_splits_payload() {
+    use std::io::{Cursor, Read};
+    use crate::{demux, mux, Fmt, Hdr};
+
+    let h = Hdr { format: Fmt::Int8, rate: 8000, channels: 1,
+                 ..Default::default() };
+    let pcm: Vec<u8> = (0..4000u32).map(|i| (i % 251) as u8).collect();
+
+    let mut rkout = mux(Vec::new(), h, Some(3)).unwrap();
+    rkout.write_all(&pcm[..1500]).unwrap();
+    rkout.flush_frame().unwrap();
+    rkout.write_all(&pcm[1500..]).unwrap();
+    let file = rkout.finish().unwrap();
+
+    let mut rkin = demux(Cursor::new(file)).unwrap();
+    let mut out = Vec::new();
+    rkin.read_to_end(&mut out).unwrap();
+
+    assert!(out == pcm);
+}
+
+#[test]
+fn write_native_is_little_endian() {
+    use crate::Fmt;
+
+    let h = Hdr { format: Fmt::Int16, rate: 8000, channels: 1,
+                  frames: None };
+    let mut w = crate::mux(Vec::new(), h, None).unwrap();
+    w.write_native(&[0x0102i16, -2]).unwrap();
+    assert!(matches!(w.write_native(&[1i32]), Err(RErr::Format)));
+    assert!(w.finish().unwrap()[2..] == [0x02, 0x01, 0xfe, 0xff]);
+
+    let h = Hdr { format: Fmt::Float32, ..h };
+    let mut w = crate::mux(Vec::new(), h, None).unwrap();
+    w.write_native(&[1.0f32]).unwrap();
+    assert!(w.finish().unwrap()[2..] == [0x00, 0x00, 0x80, 0x3f]);
+}
+
+#[test]
+fn unfinished_drop_asserts() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use crate::{Fmt, MuxOptions};
+
+    let h = Hdr { format: Fmt::Int8, rate: 8000, channels: 1,
+                  frames: None };
+    let opts = MuxOptions { pcm_crc: true, ..Default::default() };
+    fn panics<W: Write>(w: RkWriter<W>) -> bool {
+        catch_unwind(AssertUnwindSafe(|| drop(w))).is_err()
+    }
+
+    #[cfg(feature = "compression")]
+    {
+        let mut w = crate::mux(Vec::new(), h, Some(1)).unwrap();
+        w.write_all(&[1, 2, 3]).unwrap();
+        assert!(panics(w) == cfg!(debug_assertions));
+    }
+    let w = crate::mux_with_options(Vec::new(), h, opts).unwrap();
+    assert!(panics(w) == cfg!(debug_assertions));
+
+    // a plain payload is complete as written, and the others are
+    // fine given up on, or once a write to them failed.
+    assert!(!panics(crate::mux(Vec::new(), h, None).unwrap()));
+    crate::mux_with_options(Vec::new(), h, opts).unwrap().abandon();
+    let mut buf = [0u8; 4];
+    let mut w = crate::mux_with_options(&mut buf[..], h, opts).unwrap();
+    assert!(w.write_all(&[1, 2, 3]).is_err());
+    assert!(!panics(w));
+}
+
+#[test]
+fn channel_muxer_out_of_lockstep() {
+    use crate::{decode_from_slice, mux, Fmt};
+
+    let h = Hdr { format: Fmt::Float32, rate: 8000, channels: 2,
+                  frames: None };
+    let mut cm = ChannelMuxer::new(mux(Vec::new(), h, None).unwrap());
+    cm.push(0, &[0.0, 0.25, 0.5]).unwrap();
+    cm.push(1, &[-0.0]).unwrap();
+    cm.push(1, &[-0.25, -0.5, -0.75]).unwrap();
+    assert!(matches!(cm.push(2, &[1.0]), Err(RErr::Channels)));
+    cm.push(0, &[0.75]).unwrap();
+
+    let (_, pcm) = decode_from_slice(&cm.finish().unwrap()).unwrap();
+    let s: Vec<f32> = pcm.chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect();
+    assert!(s == [0.0, -0.0, 0.25, -0.25, 0.5, -0.5, 0.75, -0.75]);
+
+    let mut cm = ChannelMuxer::new(mux(Vec::new(), h, None).unwrap());
+    cm.push(0, &[0.5, 0.25]).unwrap();
+    cm.push(1, &[0.5]).unwrap();
+    assert!(cm.pending() == 1);
+    cm.discard_pending();
+    let (_, pcm) = decode_from_slice(&cm.finish().unwrap()).unwrap();
+    assert!(pcm.len() == 8);
+
+    let mut cm = ChannelMuxer::new(mux(Vec::new(), h, None).unwrap());
+    cm.push(0, &[0.5]).unwrap();
+    assert!(matches!(cm.finish(), Err(RErr::Channels)));
+}
+
+#[test]
+fn debug_without_sink() {
+    use crate::{mux, Fmt};
+
+    // a sink that isn't `Debug` itself.
+    struct Opaque;
+    impl Write for Opaque {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> { Ok(buf.len()) }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    let h = Hdr { format: Fmt::Int8, rate: 8000, channels: 2, frames: None };
+    let mut w = mux(Opaque, h, None).unwrap();
+    w.write_all(&[1, 2, 3, 4, 5]).unwrap();
+
+    let s = format!("{:?}", w);
+    assert!(s.contains("Int8") && s.contains("8000"), "{}", s);
+    assert!(s.contains("frames_written: 2"));
+    w.finish().unwrap();
+}